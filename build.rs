@@ -0,0 +1,4 @@
+// The migrations are embedded, changes to them must rebuild the binary
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS USERS
+(
+    id          BLOB        PRIMARY KEY,
+    username    TEXT        NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS USER_IDENTITIES
+(
+    provider    TEXT        NOT NULL,
+    subject     TEXT        NOT NULL,
+    user_id     BLOB        NOT NULL REFERENCES USERS (id) ON DELETE CASCADE,
+    PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS USER_IDENTITIES_USER_ID ON USER_IDENTITIES (user_id);
+
+-- Notes used to belong to the subject of the only OIDC provider, which becomes the id of its user
+INSERT OR IGNORE INTO USERS (id, username)
+SELECT DISTINCT owner, lower(
+    substr(hex(owner), 1, 8) || '-' || substr(hex(owner), 9, 4) || '-' ||
+    substr(hex(owner), 13, 4) || '-' || substr(hex(owner), 17, 4) || '-' ||
+    substr(hex(owner), 21, 12)
+)
+FROM NOTES;
+
+INSERT OR IGNORE INTO USER_IDENTITIES (provider, subject, user_id)
+SELECT 'default', username, id FROM USERS;
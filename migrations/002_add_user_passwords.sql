@@ -0,0 +1,4 @@
+ALTER TABLE USERS ADD COLUMN password TEXT;
+
+-- Local accounts sign in with their username, so it must be unique among them
+CREATE UNIQUE INDEX IF NOT EXISTS USERS_LOCAL_USERNAME ON USERS (username) WHERE password IS NOT NULL;
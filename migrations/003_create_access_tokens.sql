@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS ACCESS_TOKENS
+(
+    id              BLOB        PRIMARY KEY,
+    user_id         BLOB        NOT NULL REFERENCES USERS (id) ON DELETE CASCADE,
+    name            TEXT        NOT NULL,
+    token_hash      BLOB        NOT NULL UNIQUE,
+    scopes          TEXT        NOT NULL,
+    created_at      DATETIME    NOT NULL,
+    expires_at      DATETIME,
+    last_used_at    DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS ACCESS_TOKENS_USER_ID ON ACCESS_TOKENS (user_id);
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS SESSIONS
+(
+    id              TEXT        PRIMARY KEY,
+    data            TEXT        NOT NULL,
+    expiry_date     INTEGER     NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS SESSIONS_EXPIRY_DATE ON SESSIONS (expiry_date);
+
+-- Authenticated users of the sessions, so they survive a restart of the server. They are kept
+-- per login, so every device has its own access token.
+CREATE TABLE IF NOT EXISTS SESSION_USERS
+(
+    login_id            BLOB        PRIMARY KEY,
+    user_id             BLOB        NOT NULL REFERENCES USERS (id) ON DELETE CASCADE,
+    credentials         BLOB        NOT NULL,
+    auth_hash           BLOB        NOT NULL,
+    pending_action      TEXT,
+    expires_at          DATETIME    NOT NULL,
+    last_health_check   DATETIME    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS SESSION_USERS_USER_ID ON SESSION_USERS (user_id);
+CREATE INDEX IF NOT EXISTS SESSION_USERS_EXPIRES_AT ON SESSION_USERS (expires_at);
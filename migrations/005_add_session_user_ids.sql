@@ -0,0 +1,9 @@
+-- Sessions are listed per user on the sessions page
+ALTER TABLE SESSIONS ADD COLUMN user_id BLOB;
+
+CREATE INDEX IF NOT EXISTS SESSIONS_USER_ID ON SESSIONS (user_id);
+
+-- Revoking a session also ends its login
+ALTER TABLE SESSIONS ADD COLUMN login_id BLOB;
+
+CREATE INDEX IF NOT EXISTS SESSIONS_LOGIN_ID ON SESSIONS (login_id);
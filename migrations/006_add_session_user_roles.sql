@@ -0,0 +1,2 @@
+-- Space separated list of the application roles of the user
+ALTER TABLE SESSION_USERS ADD COLUMN roles TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,2 @@
+-- Disabled accounts can't sign in, neither interactively nor with access tokens
+ALTER TABLE USERS ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,19 @@
+-- Content Security Policy violations reported by browsers, repeated reports only bump the count
+CREATE TABLE IF NOT EXISTS CSP_REPORTS
+(
+    id              INTEGER     PRIMARY KEY AUTOINCREMENT,
+    document_uri    TEXT        NOT NULL,
+    directive       TEXT        NOT NULL,
+    blocked_uri     TEXT        NOT NULL,
+    source_file     TEXT        NOT NULL DEFAULT '',
+    line_number     INTEGER     NOT NULL DEFAULT 0,
+    disposition     TEXT        NOT NULL,
+    sample          TEXT,
+    user_agent      TEXT,
+    count           INTEGER     NOT NULL DEFAULT 1,
+    first_seen_at   DATETIME    NOT NULL,
+    last_seen_at    DATETIME    NOT NULL,
+    UNIQUE (document_uri, directive, blocked_uri, source_file, line_number, disposition)
+);
+
+CREATE INDEX IF NOT EXISTS CSP_REPORTS_LAST_SEEN_AT ON CSP_REPORTS (last_seen_at);
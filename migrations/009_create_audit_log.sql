@@ -0,0 +1,26 @@
+-- Security relevant events, the log is append-only. Users are not referenced, so their
+-- events outlive them.
+CREATE TABLE IF NOT EXISTS AUDIT_LOG
+(
+    id              INTEGER     PRIMARY KEY AUTOINCREMENT,
+    occurred_at     DATETIME    NOT NULL,
+    event           TEXT        NOT NULL,
+    user_id         BLOB,
+    actor_id        BLOB,
+    ip              TEXT,
+    user_agent      TEXT,
+    details         TEXT        NOT NULL DEFAULT ''
+);
+
+CREATE INDEX IF NOT EXISTS AUDIT_LOG_OCCURRED_AT ON AUDIT_LOG (occurred_at);
+CREATE INDEX IF NOT EXISTS AUDIT_LOG_USER_ID ON AUDIT_LOG (user_id);
+
+CREATE TRIGGER IF NOT EXISTS AUDIT_LOG_NO_UPDATE BEFORE UPDATE ON AUDIT_LOG
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS AUDIT_LOG_NO_DELETE BEFORE DELETE ON AUDIT_LOG
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
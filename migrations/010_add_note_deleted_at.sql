@@ -0,0 +1,3 @@
+-- Deleted notes stay in the trash until `purge-trash` removes them
+ALTER TABLE NOTES ADD COLUMN deleted_at DATETIME;
+CREATE INDEX IF NOT EXISTS NOTES_DELETED_AT ON NOTES (deleted_at);
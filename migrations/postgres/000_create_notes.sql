@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS Notes
+(
+    id          UUID        PRIMARY KEY,
+    owner       UUID        NOT NULL,
+    content     TEXT        NOT NULL,
+    checked     BOOLEAN     NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS notes_owner ON Notes (owner);
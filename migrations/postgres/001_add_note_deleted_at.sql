@@ -0,0 +1,3 @@
+-- Deleted notes stay in the trash until `purge-trash` removes them
+ALTER TABLE Notes ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS notes_deleted_at ON Notes (deleted_at);
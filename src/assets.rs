@@ -0,0 +1,116 @@
+use axum::{
+    extract::Request,
+    http::{
+        HeaderMap, HeaderValue, Method, StatusCode,
+        header::{
+            ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            VARY,
+        },
+    },
+    response::{IntoResponse, Response},
+};
+use rust_embed::{EmbeddedFile, RustEmbed};
+
+/// Output of the Vite build, embedded when the binary is compiled. Debug builds read it from
+/// disk, so a rebuild of the frontend doesn't require one of the server.
+#[derive(RustEmbed)]
+#[folder = "dist/"]
+struct Dist;
+
+/// Vite puts the files with a content hash in their name into this directory.
+const HASHED_ASSETS: &str = "assets/";
+
+/// Precompressed variants by content encoding, in order of preference.
+const ENCODINGS: &[(&str, &str)] = &[("br", ".br"), ("gzip", ".gz")];
+
+/// Serves the embedded frontend, precompressed if the client accepts it. Files with a content
+/// hash are cached forever, the others are revalidated with their ETag.
+pub(crate) async fn serve_embedded(request: Request) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+
+    let mut path = request.uri().path().trim_start_matches('/').to_string();
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
+    }
+
+    let Some(original) = Dist::get(&path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let content_type = original.metadata.mimetype().to_string();
+
+    let (file, encoding) = ENCODINGS
+        .iter()
+        .filter(|(encoding, _)| accepts(request.headers(), encoding))
+        .find_map(|(encoding, extension)| {
+            Dist::get(&format!("{path}{extension}")).map(|file| (file, Some(*encoding)))
+        })
+        .unwrap_or((original, None));
+
+    let etag = etag(&file);
+    let cache_control = if path.starts_with(HASHED_ASSETS) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ETAG, HeaderValue::from_str(&etag).unwrap());
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+
+    if is_fresh(request.headers(), &etag) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    if let Ok(content_type) = HeaderValue::from_str(&content_type) {
+        headers.insert(CONTENT_TYPE, content_type);
+    }
+    if let Some(encoding) = encoding {
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    }
+
+    (headers, file.data).into_response()
+}
+
+/// Strong ETag of the served variant, as every variant has different bytes.
+fn etag(file: &EmbeddedFile) -> String {
+    let hash: String = file.metadata.sha256_hash()[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    format!("\"{hash}\"")
+}
+
+fn accepts(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next()?;
+            // Only an explicit quality of 0 rules the encoding out
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    .is_some_and(|quality| quality <= 0.0)
+            });
+            (!refused).then_some(name)
+        })
+        .any(|name| name.eq_ignore_ascii_case(encoding) || name == "*")
+}
+
+fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
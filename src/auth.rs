@@ -1,33 +1,282 @@
-use std::str::FromStr;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
 
 use async_stream::stream;
 use axum::{
-    extract::{Query, State},
-    http::{Method, StatusCode},
-    response::{IntoResponse, Redirect},
+    Extension,
+    extract::{ConnectInfo, FromRequestParts, OriginalUri, Path, Query, Request, State},
+    http::{
+        Method, StatusCode,
+        header::{AUTHORIZATION, USER_AGENT, WWW_AUTHENTICATE},
+        request::Parts,
+    },
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use chrono::{TimeDelta, Utc};
+use datastar::{
+    Sse,
+    axum::ReadSignals,
+    consts::FragmentMergeMode,
+    prelude::{MergeFragments, MergeSignals},
 };
-use datastar::{Sse, axum::ReadSignals, consts::FragmentMergeMode, prelude::MergeFragments};
-use regex::Regex;
 use serde::Deserialize;
-use tracing::{debug, error, info, warn};
-use uuid::Uuid;
+use tower_sessions::Session;
+use tracing::{error, info, warn};
 
 use crate::{
-    model::{ApplicationSignals, PendingAction},
-    service::{AuthenticationCredentials, LoginCallback, NoteService, OidcAuthBackend, OidcState},
+    client_ip::ClientIp,
+    csrf::CsrfToken,
+    fragments::FormMessageFragment,
+    headers::CspNonce,
+    model::{
+        AccessToken, AuditEvent, AuditRecord, PendingAction, SessionDevice, SessionUser,
+        TokenScope, UserId,
+    },
+    replay::Replayer,
+    service::{
+        AccessTokenService, AuditLogService, AuthError, AuthenticationCredentials, LoginCallback,
+        OidcAuthBackend, OidcState, PasswordCredentials,
+    },
+    view,
 };
 
 pub(crate) type AuthSession = axum_login::AuthSession<OidcAuthBackend>;
 
+/// `last_seen_at` of a session is only written if the stored value is older than this.
+const LAST_SEEN_RESOLUTION: TimeDelta = TimeDelta::minutes(1);
+
+/// The user a request is made on behalf of, either signed in or via personal access token.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CurrentUser {
+    pub id: UserId,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<CurrentUser>()
+            .copied()
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Device information of the client, recorded when a session signs in.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            user_agent: parts
+                .headers
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            ip: match parts.extensions.get::<ClientIp>() {
+                Some(ClientIp(ip)) => ip.map(|ip| ip.to_string()),
+                None => parts
+                    .extensions
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(address)| address.ip().to_string()),
+            },
+        })
+    }
+}
+
+impl ClientInfo {
+    /// Address of the client, parsed for rate limiting.
+    pub fn ip_addr(&self) -> Option<IpAddr> {
+        self.ip.as_deref().and_then(|ip| ip.parse().ok())
+    }
+
+    /// Starts an audit log record of an event caused by this client.
+    pub fn audit(&self, event: AuditEvent) -> AuditRecord {
+        AuditRecord::new(event).client(self.ip.clone(), self.user_agent.clone())
+    }
+}
+
+/// Signs the user in and records the device of the session.
+async fn login_user(
+    auth_session: &mut AuthSession,
+    session: &Session,
+    user: &SessionUser,
+    client: ClientInfo,
+) -> Result<(), axum_login::Error<OidcAuthBackend>> {
+    auth_session.login(user).await?;
+
+    let now = Utc::now();
+    let device = SessionDevice {
+        user_id: user.id,
+        login_id: user.login_id,
+        user_agent: client.user_agent,
+        ip: client.ip,
+        logged_in_at: now,
+        last_seen_at: now,
+    };
+
+    session
+        .insert(SessionDevice::SESSION_KEY, device)
+        .await
+        .map_err(axum_login::Error::Session)
+}
+
+/// Keeps the last activity of signed in sessions up to date.
+pub(crate) async fn track_activity(session: Session, request: Request, next: Next) -> Response {
+    match session
+        .get::<SessionDevice>(SessionDevice::SESSION_KEY)
+        .await
+    {
+        Ok(Some(mut device)) => {
+            let now = Utc::now();
+            if device.last_seen_at + LAST_SEEN_RESOLUTION < now {
+                device.last_seen_at = now;
+
+                if let Err(err) = session.insert(SessionDevice::SESSION_KEY, device).await {
+                    warn!("Failed to update session activity: {:?}", err);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(err) => warn!("Failed to read session device: {:?}", err),
+    }
+
+    next.run(request).await
+}
+
+/// Records responses that denied access in the audit log, e.g. for a missing role, a token
+/// without the needed scope or a failed CSRF check.
+pub(crate) async fn audit_denials(
+    State(audit): State<AuditLogService>,
+    auth_session: AuthSession,
+    client: ClientInfo,
+    request: Request,
+    next: Next,
+) -> Response {
+    let request_line = format!("{} {}", request.method(), request.uri().path());
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::FORBIDDEN {
+        let mut record = client
+            .audit(AuditEvent::PermissionDenied)
+            .details(request_line);
+        if let Some(user) = &auth_session.user {
+            record = record.user(user.id);
+        }
+
+        audit.record(record).await;
+    }
+
+    response
+}
+
+/// A request authenticated by a valid personal access token, without a signed in user. Only
+/// these requests are exempt from the CSRF check, as a browser can't send the token on its own.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenAuthentication(pub AccessToken);
+
+/// Checks the personal access token of an `Authorization: Bearer` header. Invalid tokens are
+/// rejected, so a garbage header can't be used to skip the CSRF check.
+pub(crate) async fn authenticate_token(
+    State(tokens): State<AccessTokenService>,
+    auth_session: AuthSession,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let bearer = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    let Some(bearer) = bearer else {
+        return next.run(request).await;
+    };
+
+    let token = match tokens.authenticate(bearer).await {
+        Ok(Some(token)) => token,
+        Ok(None) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, "Bearer error=\"invalid_token\"")],
+            )
+                .into_response();
+        }
+        Err(error) => {
+            error!("Failed to authenticate access token: {}", error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // The session of a signed in user takes precedence and stays protected
+    if auth_session.user.is_none() {
+        request.extensions_mut().insert(TokenAuthentication(token));
+    }
+
+    next.run(request).await
+}
+
+/// Like `login_required!`, but also accepts requests authenticated by [`authenticate_token`].
+/// Sets the [`CurrentUser`] of the request.
+pub(crate) async fn require_user(
+    auth_session: AuthSession,
+    OriginalUri(original_uri): OriginalUri,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .extensions()
+        .get::<TokenAuthentication>()
+        .map(|TokenAuthentication(token)| token);
+
+    let user = if let Some(token) = token {
+        let scope = if request.method().is_safe() {
+            TokenScope::Read
+        } else {
+            TokenScope::Write
+        };
+
+        if !token.allows(scope) {
+            warn!("Access token {} lacks the {:?} scope", token.id, scope);
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        CurrentUser { id: token.user_id }
+    } else if let Some(user) = auth_session.user {
+        CurrentUser { id: user.id }
+    } else {
+        return match axum_login::url_with_redirect_query("/login", "next", original_uri) {
+            Ok(login_url) => Redirect::temporary(&login_url.to_string()).into_response(),
+            Err(err) => {
+                error!(err = %err);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
+    };
+
+    request.extensions_mut().insert(user);
+    next.run(request).await
+}
+
 pub(crate) async fn login_callback(
     mut auth_session: AuthSession,
+    session: Session,
+    State(audit): State<AuditLogService>,
     Query(query): Query<LoginCallback>,
-    State(notes): State<NoteService>,
+    Extension(replayer): Extension<Replayer>,
+    client: ClientInfo,
 ) -> impl IntoResponse {
-    if auth_session.user.is_some() {
-        info!("User is already authenticated, redirecting to home page.");
-        return Redirect::temporary("/login").into_response();
-    }
+    // Authenticated users only come back here after linking another identity
+    let linking = auth_session.user.is_some();
 
     return match auth_session
         .authenticate(AuthenticationCredentials::LoginCallback(query))
@@ -35,59 +284,106 @@ pub(crate) async fn login_callback(
     {
         Ok(user) => {
             let mut user = user.unwrap();
+            let record = client.audit(AuditEvent::LoginSucceeded).user(user.id);
 
-            if let Err(err) = auth_session.login(&user).await {
+            if let Err(err) = login_user(&mut auth_session, &session, &user, client).await {
                 error!("Failed to login user: {:?}", err);
 
                 StatusCode::INTERNAL_SERVER_ERROR.into_response()
             } else {
-                // TODO: Find a better spot for this:
-                if let Some(action) = user.pending_action {
-                    debug!("Processing pending action for user");
-
-                    match action {
-                        PendingAction::CheckNote(note_id) => {
-                            let _ = notes.update_note_checked(user.id, note_id, true).await;
-                        }
-                        PendingAction::UncheckNote(note_id) => {
-                            let _ = notes.update_note_checked(user.id, note_id, false).await;
-                        }
-                        PendingAction::EditNote(note_id, content) => {
-                            let _ = notes.update_note_content(user.id, note_id, &content).await;
-                        }
-                        PendingAction::DeleteNote(note_id) => {
-                            let _ = notes.delete_note(user.id, note_id).await;
-                        }
-                        PendingAction::CreateNote(content) => {
-                            let _ = notes.create_note(user.id, &content).await;
-                        }
-                    };
-
-                    user.pending_action = None;
+                match auth_session.backend.take_pending_action(&mut user).await {
+                    Ok(Some(action)) => replayer.replay(&session, user.id, action).await,
+                    Ok(None) => {}
+                    Err(err) => error!("Failed to clear pending action: {:?}", err),
                 }
 
-                Redirect::temporary("/").into_response()
+                let details = if linking { "linked identity" } else { "oidc" };
+                audit.record(record.details(details)).await;
+
+                if linking {
+                    info!("Linked identity, redirecting to account page.");
+                    Redirect::temporary("/account").into_response()
+                } else {
+                    Redirect::temporary("/").into_response()
+                }
             }
         }
         Err(e) => {
             warn!("Authentication failed: {:?}", e);
+            audit
+                .record(client.audit(AuditEvent::LoginFailed).details(&e))
+                .await;
+
             Redirect::temporary("/login/error").into_response()
         }
     };
 }
 
-pub(crate) async fn login(auth_session: AuthSession) -> impl IntoResponse {
+pub(crate) async fn login(
+    auth_session: AuthSession,
+    CsrfToken(csrf_token): CsrfToken,
+    CspNonce(csp_nonce): CspNonce,
+) -> impl IntoResponse {
     if auth_session.user.is_some() {
-        Redirect::temporary("/").into_response()
-    } else {
-        Redirect::temporary(
-            auth_session
-                .backend
-                .get_authentication_url(OidcState::default())
-                .await
-                .as_str(),
-        )
-        .into_response()
+        return Redirect::temporary("/").into_response();
+    }
+
+    match (
+        auth_session.backend.providers(),
+        auth_session.backend.local(),
+    ) {
+        ([provider], None) => {
+            authentication_redirect(&auth_session, &provider.id, None, None).await
+        }
+        (providers, local) => {
+            view::login::provider_chooser(providers, local, csrf_token, csp_nonce).into_response()
+        }
+    }
+}
+
+pub(crate) async fn login_provider(
+    auth_session: AuthSession,
+    session: Session,
+    Path(provider): Path<String>,
+) -> impl IntoResponse {
+    if auth_session.user.is_some() {
+        return Redirect::temporary("/").into_response();
+    }
+
+    let action = take_pending_action(&session).await;
+    authentication_redirect(&auth_session, &provider, action, None).await
+}
+
+pub(crate) async fn link_provider(
+    auth_session: AuthSession,
+    Path(provider): Path<String>,
+) -> impl IntoResponse {
+    let user = auth_session
+        .user
+        .as_ref()
+        .expect("User must be logged in to use this endpoint");
+
+    authentication_redirect(&auth_session, &provider, None, Some(user.id)).await
+}
+
+async fn authentication_redirect(
+    auth_session: &AuthSession,
+    provider: &str,
+    action: Option<PendingAction>,
+    link_to: Option<UserId>,
+) -> Response {
+    let state = OidcState {
+        provider: provider.to_string(),
+        action,
+        link_to,
+    };
+
+    match auth_session.backend.get_authentication_url(state).await {
+        Ok(url) => Redirect::temporary(url.as_str()).into_response(),
+        Err(error) => {
+            warn!("Unable to start authentication: {}", error);
+            StatusCode::NOT_FOUND.into_response()
+        }
     }
 }
 
@@ -96,52 +392,75 @@ pub(crate) struct NextQuery {
     next: Option<String>,
 }
 
+/// Session key of the [`PendingAction`] while the user picks a login method.
+const PENDING_ACTION_KEY: &str = "pending_action";
+
 pub(crate) async fn login_datastar(
     method: Method,
     auth_session: AuthSession,
+    session: Session,
     Query(query): Query<NextQuery>,
-    ReadSignals(signals): ReadSignals<ApplicationSignals>,
+    ReadSignals(signals): ReadSignals<serde_json::Value>,
 ) -> impl IntoResponse {
-    let uri = if let Some(next) = query.next {
-        // Convert query parameter to respective action
-        let regex = Regex::new(
-            r"^\/note\/([0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12})(\/:(check|uncheck))?$",
-        ).unwrap();
-
-        let action = if let Some(capture_group) = regex.captures(&next) {
-            let id = Uuid::from_str(capture_group.get(1).unwrap().as_str()).unwrap();
-
-            match capture_group.get(3).map(|m| m.as_str()).unwrap_or("") {
-                "check" => Some(PendingAction::CheckNote(id)),
-                "uncheck" => Some(PendingAction::UncheckNote(id)),
-                "" if method == Method::DELETE => Some(PendingAction::DeleteNote(id)),
-                "" if method == Method::PUT => Some(PendingAction::EditNote(id, signals.note)),
-
-                _ => {
-                    warn!("Couldn't parse the next query parameter: {}", next);
-                    None
-                },
-            }
-        } else if next == "/note" {
-            Some(PendingAction::CreateNote(signals.note))
-        } else {
-            // Should this even be possible?
-            warn!("Login redirect for a PUT/POST/DELETE method, but no action!");
-            None
-        };
+    // Only requests to this application can be replayed
+    let action = query
+        .next
+        .filter(|next| next.starts_with('/') && !next.starts_with("//"))
+        .map(|path| PendingAction {
+            method: method.to_string(),
+            path,
+            signals,
+        });
 
-        auth_session
+    let Some(action) = action else {
+        warn!("Login redirect for a {} request, but no action!", method);
+        return datastar_redirect("/login");
+    };
+
+    let uri = match (
+        auth_session.backend.providers(),
+        auth_session.backend.local(),
+    ) {
+        ([provider], None) => auth_session
             .backend
-            .get_authentication_url(OidcState { action })
+            .get_authentication_url(OidcState {
+                provider: provider.id.clone(),
+                action: Some(action),
+                link_to: None,
+            })
             .await
-    } else {
-        "/login".to_string()
+            .unwrap_or("/login".to_string()),
+        _ => {
+            // The login method is only known after the user picked one on the chooser
+            if let Err(err) = session.insert(PENDING_ACTION_KEY, action).await {
+                error!("Failed to store pending action in session: {:?}", err);
+            }
+
+            "/login".to_string()
+        }
     };
 
-    // Redirection with a meta tag to avoid CSP issues
     // TODO: Can this be handled via a pop-up window?
+    datastar_redirect(&uri)
+}
+
+/// Removes the action the user was redirected to the chooser with from the session.
+async fn take_pending_action(session: &Session) -> Option<PendingAction> {
+    session
+        .remove(PENDING_ACTION_KEY)
+        .await
+        .unwrap_or_else(|err| {
+            warn!("Failed to read pending action from session: {:?}", err);
+            None
+        })
+}
+
+/// Redirects a Datastar request with a meta tag to avoid CSP issues.
+pub(crate) fn datastar_redirect(uri: &str) -> Response {
+    let fragment = format!("<meta http-equiv='Refresh' content='0; URL={uri}'/>");
+
     Sse(stream! {
-        yield MergeFragments::new(format!("<meta http-equiv='Refresh' content='0; URL={uri}'/>"))
+        yield MergeFragments::new(fragment)
             .merge_mode(FragmentMergeMode::Append)
             .selector("head")
             .into();
@@ -149,6 +468,242 @@ pub(crate) async fn login_datastar(
     .into_response()
 }
 
+fn form_message(message: FormMessageFragment) -> Response {
+    Sse(stream! {
+        yield message.fragment().unwrap().into();
+    })
+    .into_response()
+}
+
+/// Errors of local accounts that can be shown to the user as they are.
+fn is_user_facing(error: &AuthError) -> bool {
+    matches!(
+        error,
+        AuthError::InvalidCredentials
+            | AuthError::AccountDisabled
+            | AuthError::SignupDisabled
+            | AuthError::InvalidUsername
+            | AuthError::UsernameTaken
+            | AuthError::WeakPassword(_)
+    )
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PasswordLoginSignals {
+    username: String,
+    password: String,
+}
+
+pub(crate) async fn password_login(
+    mut auth_session: AuthSession,
+    session: Session,
+    State(audit): State<AuditLogService>,
+    Extension(replayer): Extension<Replayer>,
+    client: ClientInfo,
+    ReadSignals(signals): ReadSignals<PasswordLoginSignals>,
+) -> impl IntoResponse {
+    const MESSAGE_ID: &str = "login-message";
+
+    // Failed attempts are recorded with the username that was tried
+    let username = signals.username.clone();
+    let failure = |reason: String| {
+        client
+            .audit(AuditEvent::LoginFailed)
+            .details(format!("{reason}, username {username}"))
+    };
+
+    let credentials = AuthenticationCredentials::Password(PasswordCredentials {
+        username: signals.username,
+        password: signals.password,
+    });
+
+    match auth_session.authenticate(credentials).await {
+        Ok(Some(user)) => {
+            let record = client
+                .audit(AuditEvent::LoginSucceeded)
+                .user(user.id)
+                .details("password");
+
+            if let Err(err) = login_user(&mut auth_session, &session, &user, client.clone()).await {
+                error!("Failed to login user: {:?}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            audit.record(record).await;
+
+            if let Some(action) = take_pending_action(&session).await {
+                replayer.replay(&session, user.id, action).await;
+            }
+
+            datastar_redirect("/")
+        }
+        Ok(None) => {
+            let reason = AuthError::InvalidCredentials;
+            audit.record(failure(reason.to_string())).await;
+
+            form_message(FormMessageFragment::error(MESSAGE_ID, reason))
+        }
+        Err(axum_login::Error::Backend(error)) if is_user_facing(&error) => {
+            audit.record(failure(error.to_string())).await;
+
+            form_message(FormMessageFragment::error(MESSAGE_ID, error))
+        }
+        Err(e) => {
+            warn!("Authentication failed: {:?}", e);
+            audit.record(failure(e.to_string())).await;
+
+            form_message(FormMessageFragment::error(
+                MESSAGE_ID,
+                "Unable to sign in, please try again.",
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SignupSignals {
+    username: String,
+    password: String,
+    confirmation: String,
+}
+
+pub(crate) async fn signup(
+    mut auth_session: AuthSession,
+    session: Session,
+    State(audit): State<AuditLogService>,
+    client: ClientInfo,
+    ReadSignals(signals): ReadSignals<SignupSignals>,
+) -> impl IntoResponse {
+    const MESSAGE_ID: &str = "signup-message";
+
+    let Some(local) = auth_session.backend.local().cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if signals.password != signals.confirmation {
+        return form_message(FormMessageFragment::error(
+            MESSAGE_ID,
+            "The passwords don't match.",
+        ));
+    }
+
+    let session_user = match local.signup(&signals.username, signals.password).await {
+        Ok(user) => auth_session.backend.password_session(&user).await,
+        Err(error) => Err(error),
+    };
+
+    match session_user {
+        Ok(user) => {
+            let record = client
+                .audit(AuditEvent::LoginSucceeded)
+                .user(user.id)
+                .details("signup");
+
+            if let Err(err) = login_user(&mut auth_session, &session, &user, client).await {
+                error!("Failed to login user: {:?}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            audit.record(record).await;
+
+            datastar_redirect("/")
+        }
+        Err(error) if is_user_facing(&error) => {
+            form_message(FormMessageFragment::error(MESSAGE_ID, error))
+        }
+        Err(error) => {
+            error!("Failed to create account: {:?}", error);
+            form_message(FormMessageFragment::error(
+                MESSAGE_ID,
+                "Unable to create the account, please try again.",
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ChangePasswordSignals {
+    current: String,
+    password: String,
+    confirmation: String,
+}
+
+pub(crate) async fn change_password(
+    mut auth_session: AuthSession,
+    State(audit): State<AuditLogService>,
+    client: ClientInfo,
+    ReadSignals(signals): ReadSignals<ChangePasswordSignals>,
+) -> impl IntoResponse {
+    const MESSAGE_ID: &str = "password-message";
+
+    let user = auth_session
+        .user
+        .clone()
+        .expect("User must be logged in to use this endpoint");
+
+    let Some(local) = auth_session.backend.local().cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if signals.password != signals.confirmation {
+        return form_message(FormMessageFragment::error(
+            MESSAGE_ID,
+            "The passwords don't match.",
+        ));
+    }
+
+    let session_user = match local
+        .change_password(user.id, signals.current, signals.password)
+        .await
+    {
+        Ok(account) => {
+            auth_session
+                .backend
+                .password_login(&account, user.login_id)
+                .await
+        }
+        Err(error) => Err(error),
+    };
+
+    match session_user {
+        Ok(session_user) => {
+            // The session keeps its login with the new session hash, all other logins end
+            if let Err(err) = auth_session.login(&session_user).await {
+                error!("Failed to login user: {:?}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            if let Err(err) = auth_session
+                .backend
+                .sign_out_other_logins(session_user.key())
+                .await
+            {
+                error!("Failed to sign out the other sessions: {:?}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            audit
+                .record(client.audit(AuditEvent::PasswordChanged).user(user.id))
+                .await;
+
+            Sse(stream! {
+                yield MergeSignals::new("{ current: '', password: '', confirmation: '' }").into();
+                yield FormMessageFragment::success(MESSAGE_ID, "Your password was changed.")
+                    .fragment()
+                    .unwrap()
+                    .into();
+            })
+            .into_response()
+        }
+        Err(error) if is_user_facing(&error) => {
+            form_message(FormMessageFragment::error(MESSAGE_ID, error))
+        }
+        Err(error) => {
+            error!("Failed to change password: {:?}", error);
+            form_message(FormMessageFragment::error(
+                MESSAGE_ID,
+                "Unable to change the password, please try again.",
+            ))
+        }
+    }
+}
+
 pub(crate) async fn login_error() -> impl IntoResponse {
     "Login error, please try again.".into_response()
 }
@@ -0,0 +1,620 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+};
+
+use chrono::TimeDelta;
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    PgPool,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, DatabaseConfig},
+    db::{self, MigrationState},
+    model::{AuditEvent, Note, NoteId, Role, StoredNote, TokenScope, User, UserId, UserIdentity},
+    repository::{MaintenanceRepository, PostgresNoteRepository, RepositoryError, UserRepository},
+    service::{BackupError, BackupService, NoteService, hash_password, list_backups},
+};
+
+/// Version of the format written by `export-user`.
+const EXPORT_VERSION: u32 = 1;
+
+/// Credentials of the user created by `seed`.
+const DEMO_USERNAME: &str = "demo";
+const DEMO_PASSWORD: &str = "demo-password";
+
+const DEMO_NOTES: &[(&str, bool)] = &[
+    ("Buy milk", false),
+    ("Water the plants", true),
+    ("Read the Datastar documentation", false),
+    ("Book train tickets", false),
+];
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file, defaults to `CONFIG_FILE` or `config.toml`
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Print the effective configuration with secrets redacted and exit
+    #[arg(long, global = true)]
+    pub print_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the server, the default without a command
+    Serve,
+    /// Apply pending database migrations
+    Migrate {
+        /// Only report which migrations are applied
+        #[arg(long)]
+        status: bool,
+    },
+    /// Copy the notes including the trash from the SQLite database at `database.sqlite_url` into
+    /// PostgreSQL at `database.url`, notes that exist there are kept
+    MigrateData,
+    /// Copy the database into a new file, the server may keep running. Without a file the
+    /// backup is written to `database.backup.dir` and old backups are deleted
+    Backup { file: Option<PathBuf> },
+    /// Replace the database with a backup, the server must be stopped. Without a file the
+    /// latest backup of `database.backup.dir` is restored
+    Restore {
+        file: Option<PathBuf>,
+        /// Replace an existing database
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write a user with its identities and notes to a JSON file
+    ExportUser { id: Uuid, file: PathBuf },
+    /// Create a user from a file written by `export-user`
+    Import { file: PathBuf },
+    /// Remove the notes that have been in the trash for longer than `days` for good
+    PurgeTrash {
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+    },
+    /// Check the database for corruption and rows that don't match the model
+    Check,
+    /// Create a demo user with notes for local development
+    Seed,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum CliError {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+
+    #[error(transparent)]
+    Migration(#[from] sqlx::migrate::MigrateError),
+
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Backup(#[from] BackupError),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Failed(String),
+}
+
+/// A user as written by `export-user`, password hashes are included so local accounts keep
+/// working after an import.
+#[derive(Debug, Serialize, Deserialize)]
+struct UserExport {
+    version: u32,
+    id: UserId,
+    username: String,
+    password: Option<String>,
+    disabled: bool,
+    identities: Vec<ExportedIdentity>,
+    notes: Vec<ExportedNote>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedIdentity {
+    provider: String,
+    subject: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedNote {
+    content: String,
+    checked: bool,
+}
+
+/// Runs an administrative command, `serve` is handled by the binary.
+pub async fn run(command: Command, config: &Config) -> ExitCode {
+    let result = match command {
+        // Started by the binary, it needs the whole application
+        Command::Serve => Ok(()),
+        Command::Migrate { status } => migrate(&config.database, status).await,
+        Command::MigrateData => migrate_data(&config.database).await,
+        Command::Backup { file } => backup(&config.database, file.as_deref()).await,
+        Command::Restore { file, force } => restore(&config.database, file.as_deref(), force).await,
+        Command::ExportUser { id, file } => export_user(&config.database, UserId(id), &file).await,
+        Command::Import { file } => import(&config.database, &file).await,
+        Command::PurgeTrash { days } => purge_trash(&config.database, days).await,
+        Command::Check => check(&config.database).await,
+        Command::Seed => seed(&config.database).await,
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn migrate(config: &DatabaseConfig, status: bool) -> Result<(), CliError> {
+    let db = db::connect(config).await;
+
+    if !status {
+        db::migrator().run(&db).await?;
+        if let Some(url) = config.postgres_notes_url() {
+            db::create_postgres_pool(url).await.close().await;
+        }
+        println!("The database is up to date");
+        return Ok(());
+    }
+
+    for (version, description, state) in db::migration_status(&db).await? {
+        println!("{version:>4}  {description:<40} {state}");
+    }
+
+    Ok(())
+}
+
+async fn migrate_data(config: &DatabaseConfig) -> Result<(), CliError> {
+    let Some(url) = config.postgres_notes_url() else {
+        return Err(CliError::Failed(
+            "database.url must be a postgres: URL to copy the notes to".to_string(),
+        ));
+    };
+
+    // The notes are read from the SQLite database even though the configuration points to
+    // PostgreSQL, they stay there so the configuration can be reverted
+    let sqlite = db::create_pool(config).await;
+    let notes = MaintenanceRepository::new(sqlite.clone())
+        .find_all_stored_notes()
+        .await?;
+    sqlite.close().await;
+
+    let postgres = db::create_postgres_pool(url).await;
+    let copied = PostgresNoteRepository::new(postgres.clone())
+        .import(&notes)
+        .await?;
+    postgres.close().await;
+
+    println!(
+        "Copied {} of {} notes to PostgreSQL, the others already existed",
+        copied,
+        notes.len()
+    );
+    Ok(())
+}
+
+async fn backup(config: &DatabaseConfig, file: Option<&Path>) -> Result<(), CliError> {
+    warn_about_postgres_notes(config);
+
+    let Some(file) = file else {
+        let Some(backup) = &config.backup else {
+            return Err(CliError::Failed(
+                "Pass a file or configure database.backup.dir".to_string(),
+            ));
+        };
+
+        let db = db::connect(config).await;
+        let path = BackupService::new(MaintenanceRepository::new(db), backup.clone())
+            .backup()
+            .await?;
+
+        println!("Wrote a backup to {}", path.display());
+        return Ok(());
+    };
+
+    if file.exists() {
+        return Err(CliError::Failed(format!(
+            "{} already exists",
+            file.display()
+        )));
+    }
+
+    let db = db::connect(config).await;
+    MaintenanceRepository::new(db).backup(file).await?;
+
+    println!("Wrote a backup to {}", file.display());
+    Ok(())
+}
+
+async fn restore(
+    config: &DatabaseConfig,
+    file: Option<&Path>,
+    force: bool,
+) -> Result<(), CliError> {
+    warn_about_postgres_notes(config);
+
+    let file = match file {
+        Some(file) => file.to_path_buf(),
+        None => latest_backup(config).await?,
+    };
+
+    // The backup is checked before it replaces anything
+    let backup = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(SqliteConnectOptions::new().filename(&file).read_only(true))
+        .await?;
+    let problems = MaintenanceRepository::new(backup.clone())
+        .integrity_check()
+        .await?;
+    backup.close().await;
+    if !problems.is_empty() {
+        return Err(CliError::Failed(format!(
+            "{} is damaged: {}",
+            file.display(),
+            problems.join(", ")
+        )));
+    }
+
+    // Running servers share the lock, it is only available once they stopped
+    let Some(_lock) = db::lock_database(config, true)? else {
+        return Err(CliError::Failed(
+            "The database is in use, stop the server first".to_string(),
+        ));
+    };
+
+    let options = SqliteConnectOptions::from_str(&config.url)?;
+    let database = options.get_filename();
+    if database.exists() && !force {
+        return Err(CliError::Failed(format!(
+            "{} exists, pass --force to replace it",
+            database.display()
+        )));
+    }
+
+    std::fs::copy(&file, database)?;
+    // The journal of the replaced database must not be applied to the backup
+    for suffix in ["-wal", "-shm"] {
+        let mut journal = database.as_os_str().to_owned();
+        journal.push(suffix);
+        if Path::new(&journal).exists() {
+            std::fs::remove_file(journal)?;
+        }
+    }
+
+    // A backup of an older version is migrated right away
+    db::create_pool(config).await.close().await;
+
+    println!("Restored {} from {}", database.display(), file.display());
+    Ok(())
+}
+
+/// Backups only contain the SQLite database, the notes in PostgreSQL need a backup of their own.
+fn warn_about_postgres_notes(config: &DatabaseConfig) {
+    if config.postgres_notes_url().is_some() {
+        println!(
+            "The notes are stored in PostgreSQL and are not part of the backup, use pg_dump and pg_restore for them"
+        );
+    }
+}
+
+async fn latest_backup(config: &DatabaseConfig) -> Result<PathBuf, CliError> {
+    let Some(backup) = &config.backup else {
+        return Err(CliError::Failed(
+            "Pass a file or configure database.backup.dir".to_string(),
+        ));
+    };
+
+    match list_backups(&backup.dir).await?.into_iter().next() {
+        Some(latest) => Ok(latest.path),
+        None => Err(CliError::Failed(format!(
+            "There is no backup in {}",
+            backup.dir.display()
+        ))),
+    }
+}
+
+async fn export_user(config: &DatabaseConfig, id: UserId, file: &Path) -> Result<(), CliError> {
+    let db = db::create_pool(config).await;
+    let users = UserRepository::new(db.clone());
+    let notes = NoteService::new(db::note_storage(config, db).await);
+
+    let Some(user) = users.find_by_id(id).await? else {
+        return Err(CliError::Failed(format!("There is no user {id}")));
+    };
+
+    let export = UserExport {
+        version: EXPORT_VERSION,
+        id: user.id,
+        username: user.username,
+        password: user.password,
+        disabled: user.disabled,
+        identities: users
+            .find_identities(id)
+            .await?
+            .into_iter()
+            .map(|identity| ExportedIdentity {
+                provider: identity.provider,
+                subject: identity.subject,
+            })
+            .collect(),
+        notes: notes
+            .get_notes(id)
+            .await?
+            .into_iter()
+            .map(|note| ExportedNote {
+                content: note.content,
+                checked: note.checked,
+            })
+            .collect(),
+    };
+
+    std::fs::write(file, serde_json::to_string_pretty(&export)?)?;
+
+    println!(
+        "Exported user {} with {} notes to {}",
+        id,
+        export.notes.len(),
+        file.display()
+    );
+    Ok(())
+}
+
+async fn import(config: &DatabaseConfig, file: &Path) -> Result<(), CliError> {
+    let export: UserExport = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+    if export.version != EXPORT_VERSION {
+        return Err(CliError::Failed(format!(
+            "Unsupported export version {}",
+            export.version
+        )));
+    }
+
+    let db = db::create_pool(config).await;
+    let users = UserRepository::new(db);
+
+    if users.exists(export.id).await? {
+        return Err(CliError::Failed(format!(
+            "The user {} already exists",
+            export.id
+        )));
+    }
+
+    let user = User {
+        id: export.id,
+        username: export.username,
+        password: export.password,
+        disabled: export.disabled,
+    };
+    let identities: Vec<UserIdentity> = export
+        .identities
+        .into_iter()
+        .map(|identity| UserIdentity {
+            provider: identity.provider,
+            subject: identity.subject,
+            user_id: user.id,
+        })
+        .collect();
+    let notes: Vec<Note> = export
+        .notes
+        .into_iter()
+        .map(|note| Note {
+            id: NoteId::new_v4(),
+            owner: user.id,
+            content: note.content,
+            checked: note.checked,
+        })
+        .collect();
+
+    match config.postgres_notes_url() {
+        None => users.create_imported(&user, &identities, &notes).await?,
+        Some(url) => {
+            // The notes can't be part of the SQLite transaction, they are removed again if the
+            // user can't be created
+            let postgres = PostgresNoteRepository::new(db::create_postgres_pool(url).await);
+            let stored: Vec<StoredNote> = notes.iter().cloned().map(StoredNote::from).collect();
+            postgres.import(&stored).await?;
+
+            if let Err(err) = users.create_imported(&user, &identities, &[]).await {
+                postgres.delete_all(user.id).await?;
+                return Err(err.into());
+            }
+        }
+    }
+
+    println!("Imported user {} with {} notes", user.id, notes.len());
+    Ok(())
+}
+
+async fn purge_trash(config: &DatabaseConfig, days: u32) -> Result<(), CliError> {
+    let db = db::create_pool(config).await;
+    let notes = NoteService::new(db::note_storage(config, db).await);
+
+    let purged = notes.purge_trash(TimeDelta::days(days.into())).await?;
+
+    println!("Removed {purged} notes that were deleted more than {days} days ago");
+    Ok(())
+}
+
+async fn check(config: &DatabaseConfig) -> Result<(), CliError> {
+    let db = db::connect(config).await;
+    let repository = MaintenanceRepository::new(db.clone());
+    let mut problems = repository.integrity_check().await?;
+
+    for (table, parent) in repository.foreign_key_violations().await? {
+        problems.push(format!("{table} references a missing row of {parent}"));
+    }
+
+    for (version, description, state) in db::migration_status(&db).await? {
+        if state != MigrationState::Applied {
+            problems.push(format!("Migration {version} {description} is {state}"));
+        }
+    }
+
+    match config.postgres_notes_url() {
+        Some(url) => check_postgres_notes(url, &repository, &mut problems).await?,
+        None => {
+            let orphaned_notes = repository.count_orphaned_notes().await?;
+            if orphaned_notes > 0 {
+                problems.push(format!("{orphaned_notes} notes belong to no user"));
+            }
+            if let Err(err) = repository.find_all_notes().await {
+                problems.push(format!("Notes can't be read: {err}"));
+            }
+        }
+    }
+    let orphaned_sessions = repository.count_orphaned_sessions().await?;
+    if orphaned_sessions > 0 {
+        problems.push(format!("{orphaned_sessions} sessions belong to no user"));
+    }
+
+    // Every row must be readable into the model
+    let mut decode = |table: &str, result: Result<usize, RepositoryError>| {
+        if let Err(err) = result {
+            problems.push(format!("{table} can't be read: {err}"));
+        }
+    };
+    decode(
+        "Users",
+        repository.find_all_users().await.map(|rows| rows.len()),
+    );
+
+    match repository.find_all_access_tokens().await {
+        Ok(tokens) => {
+            for token in tokens {
+                if let Some(scope) = token
+                    .scopes
+                    .split_whitespace()
+                    .find(|scope| TokenScope::from_str(scope).is_err())
+                {
+                    problems.push(format!(
+                        "Access token {} has the unknown scope {scope}",
+                        token.id
+                    ));
+                }
+            }
+        }
+        Err(err) => problems.push(format!("Access_Tokens can't be read: {err}")),
+    }
+
+    for (user_id, roles) in repository.find_session_user_roles().await? {
+        if let Some(role) = roles
+            .split_whitespace()
+            .find(|role| Role::from_str(role).is_err())
+        {
+            problems.push(format!(
+                "The session of user {user_id} has the unknown role {role}"
+            ));
+        }
+    }
+
+    for event in repository.find_audit_events().await? {
+        if AuditEvent::from_str(&event).is_err() {
+            problems.push(format!("The audit log contains the unknown event {event}"));
+        }
+    }
+
+    if problems.is_empty() {
+        println!("No problems found");
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("{problem}");
+    }
+    Err(CliError::Failed(format!(
+        "Found {} problems",
+        problems.len()
+    )))
+}
+
+/// The notes are checked where they are stored, the ones in SQLite are left over from before
+/// `migrate-data`.
+async fn check_postgres_notes(
+    url: &str,
+    repository: &MaintenanceRepository,
+    problems: &mut Vec<String>,
+) -> Result<(), CliError> {
+    let postgres = PgPool::connect(url).await?;
+
+    for (version, description, state) in db::postgres_migration_status(&postgres).await? {
+        if state != MigrationState::Applied {
+            problems.push(format!(
+                "PostgreSQL migration {version} {description} is {state}"
+            ));
+        }
+    }
+
+    match PostgresNoteRepository::new(postgres.clone())
+        .find_all_notes()
+        .await
+    {
+        Ok(notes) => {
+            let users: HashSet<UserId> = repository
+                .find_all_users()
+                .await?
+                .into_iter()
+                .map(|user| user.id)
+                .collect();
+            let orphaned_notes = notes
+                .iter()
+                .filter(|note| !users.contains(&note.owner))
+                .count();
+            if orphaned_notes > 0 {
+                problems.push(format!(
+                    "{orphaned_notes} notes in PostgreSQL belong to no user"
+                ));
+            }
+        }
+        Err(err) => problems.push(format!("Notes in PostgreSQL can't be read: {err}")),
+    }
+
+    postgres.close().await;
+    Ok(())
+}
+
+async fn seed(config: &DatabaseConfig) -> Result<(), CliError> {
+    let db = db::create_pool(config).await;
+    let users = UserRepository::new(db.clone());
+    let notes = NoteService::new(db::note_storage(config, db).await);
+
+    if users.find_local_by_username(DEMO_USERNAME).await?.is_some() {
+        return Err(CliError::Failed(format!(
+            "The user {DEMO_USERNAME} already exists"
+        )));
+    }
+
+    let id = UserId(Uuid::new_v4());
+    let password = hash_password(DEMO_PASSWORD.to_string()).await;
+    users.create_local(id, DEMO_USERNAME, &password).await?;
+
+    for (content, checked) in DEMO_NOTES {
+        let note = notes.create_note(id, content).await?;
+        if *checked {
+            notes.update_note_checked(id, note.id, true).await?;
+        }
+    }
+
+    println!(
+        "Created the user {DEMO_USERNAME} with the password {DEMO_PASSWORD}, sign in with it if local_auth is enabled"
+    );
+    Ok(())
+}
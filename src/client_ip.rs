@@ -0,0 +1,247 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+#[cfg(unix)]
+use axum::{extract::connect_info::Connected, serve::IncomingStream};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+
+/// Address of the client, taken from the proxy headers if the peer is a trusted proxy. `None`
+/// if the peer has no address, e.g. on a Unix socket without proxy headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClientIp(pub Option<IpAddr>);
+
+/// Reverse proxies whose `Forwarded` and `X-Forwarded-For` headers are trusted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrustedProxies {
+    addresses: Arc<Vec<IpAddr>>,
+}
+
+impl TrustedProxies {
+    pub fn new(addresses: &[IpAddr]) -> Self {
+        Self {
+            addresses: Arc::new(addresses.iter().map(IpAddr::to_canonical).collect()),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.addresses.contains(&ip.to_canonical())
+    }
+
+    /// The client address of a request that came from the peer `peer`.
+    fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        match peer {
+            Some(peer) if self.contains(peer) => Some(self.forwarded_for(headers).unwrap_or(peer)),
+            _ => peer,
+        }
+    }
+
+    /// The last address of the proxy headers that isn't a trusted proxy. Addresses before it
+    /// were sent by the client and can't be trusted. `Forwarded` takes precedence.
+    fn forwarded_for(&self, headers: &HeaderMap) -> Option<IpAddr> {
+        let forwarded: Vec<Option<IpAddr>> = header_values(headers, "forwarded")
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value))
+                })
+            })
+            .collect();
+
+        let addresses = if forwarded.is_empty() {
+            header_values(headers, "x-forwarded-for")
+                .flat_map(|value| value.split(','))
+                .map(parse_node)
+                .collect()
+        } else {
+            forwarded
+        };
+
+        // An address that can't be parsed ends the chain of trust
+        addresses
+            .into_iter()
+            .rev()
+            .map_while(|ip| ip)
+            .find(|ip| !self.contains(*ip))
+    }
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+}
+
+/// Parses a node like `192.0.2.1`, `192.0.2.1:4711`, `"[2001:db8::1]:4711"` or `2001:db8::1`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+
+    node.strip_prefix('[')
+        .and_then(|node| node.split_once(']'))
+        .and_then(|(ip, _)| ip.parse().ok())
+}
+
+/// Connection info of requests over the Unix socket, its peers have no address. Only processes on
+/// the same host can connect to it, so the peer is a reverse proxy and its headers are trusted.
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UnixConnectInfo;
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, tokio::net::UnixListener>> for UnixConnectInfo {
+    fn connect_info(_: IncomingStream<'_, tokio::net::UnixListener>) -> Self {
+        Self
+    }
+}
+
+/// Whether the request came over the Unix socket, see [`UnixConnectInfo`].
+#[cfg(unix)]
+fn via_unix_socket(request: &Request) -> bool {
+    request
+        .extensions()
+        .get::<ConnectInfo<UnixConnectInfo>>()
+        .is_some()
+}
+
+#[cfg(not(unix))]
+fn via_unix_socket(_: &Request) -> bool {
+    false
+}
+
+/// Determines the [`ClientIp`] of every request, it must run before anything that uses it.
+pub(crate) async fn resolve_client_ip(
+    State(proxies): State<TrustedProxies>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip().to_canonical());
+
+    let ip = if via_unix_socket(&request) {
+        proxies.forwarded_for(request.headers())
+    } else {
+        proxies.client_ip(peer, request.headers())
+    };
+    request.extensions_mut().insert(ClientIp(ip));
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new(&["127.0.0.1".parse().unwrap(), "10.0.0.1".parse().unwrap()])
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_headers_of_untrusted_peers() {
+        let headers = headers("x-forwarded-for", "192.0.2.1");
+        assert_eq!(
+            proxies().client_ip(ip("198.51.100.1"), &headers),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn takes_the_last_untrusted_address() {
+        let headers = headers("x-forwarded-for", "203.0.113.9, 192.0.2.1, 10.0.0.1");
+        assert_eq!(
+            proxies().client_ip(ip("127.0.0.1"), &headers),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn prefers_forwarded() {
+        let mut headers = headers("forwarded", "for=\"[2001:db8::1]:4711\";proto=https");
+        headers.insert("x-forwarded-for", HeaderValue::from_static("192.0.2.1"));
+        assert_eq!(
+            proxies().client_ip(ip("::ffff:127.0.0.1"), &headers),
+            ip("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn stops_at_unparsable_addresses() {
+        let headers = headers("forwarded", "for=192.0.2.1, for=_hidden");
+        assert_eq!(
+            proxies().client_ip(ip("127.0.0.1"), &headers),
+            ip("127.0.0.1")
+        );
+    }
+
+    #[test]
+    fn parses_nodes() {
+        assert_eq!(parse_node(" 192.0.2.1:4711"), ip("192.0.2.1"));
+        assert_eq!(parse_node("2001:db8::1"), ip("2001:db8::1"));
+        assert_eq!(parse_node("\"[2001:db8::1]\""), ip("2001:db8::1"));
+        assert_eq!(parse_node("unknown"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn trusts_the_headers_of_unix_socket_peers() {
+        use axum::{Extension, Router, body::Body, middleware, routing::get};
+        use tower::ServiceExt;
+
+        let router = Router::new()
+            .route(
+                "/",
+                get(|Extension(ClientIp(ip)): Extension<ClientIp>| async move {
+                    ip.map(|ip| ip.to_string()).unwrap_or_default()
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                TrustedProxies::default(),
+                resolve_client_ip,
+            ));
+
+        let mut request = Request::builder()
+            .uri("/")
+            .header("x-forwarded-for", "192.0.2.1")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(UnixConnectInfo));
+
+        let response = router.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), 64)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"192.0.2.1");
+    }
+}
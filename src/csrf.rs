@@ -0,0 +1,385 @@
+use axum::{
+    body::{Body, to_bytes},
+    extract::{FromRequestParts, Request, State},
+    http::{HeaderMap, StatusCode, Uri, header::ORIGIN, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_sessions::Session;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::auth::TokenAuthentication;
+
+/// Session key of the CSRF token.
+const SESSION_KEY: &str = "csrf_token";
+
+/// Name of the Datastar signal carrying the token, pages define it in the layout.
+const SIGNAL: &str = "csrf";
+
+/// Header carrying the token for clients that don't send Datastar signals.
+const HEADER: &str = "x-csrf-token";
+
+/// Mutating requests with larger bodies are rejected before the token is checked.
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// Origin the application is served from, mutating requests must come from it.
+#[derive(Debug, Clone)]
+pub(crate) struct CsrfConfig {
+    origin: String,
+}
+
+impl CsrfConfig {
+    /// Derives the allowed origin from the base URL of the application.
+    pub fn new(base_url: &str) -> Self {
+        let uri: Uri = base_url.parse().expect("The base URL is validated");
+
+        Self {
+            origin: format!(
+                "{}://{}",
+                uri.scheme_str().unwrap_or("http"),
+                uri.authority().map(|a| a.as_str()).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// The CSRF token of the session, created on first use. Pages hand it to Datastar as the
+/// `csrf` signal, so it is sent along with every action.
+#[derive(Debug, Clone)]
+pub(crate) struct CsrfToken(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for CsrfToken {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state)
+            .await
+            .map_err(|(status, _)| status)?;
+
+        match session.get::<String>(SESSION_KEY).await {
+            Ok(Some(token)) => Ok(Self(token)),
+            Ok(None) => {
+                let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+
+                session.insert(SESSION_KEY, &token).await.map_err(|err| {
+                    error!("Failed to store CSRF token in session: {:?}", err);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+
+                Ok(Self(token))
+            }
+            Err(err) => {
+                error!("Failed to read CSRF token from session: {:?}", err);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// Outcome of the `Sec-Fetch-Site` and `Origin` checks.
+#[derive(Debug, PartialEq)]
+enum Origin {
+    Same,
+    Cross,
+    /// Neither header was sent, e.g. by older browsers.
+    Unknown,
+}
+
+fn request_origin(headers: &HeaderMap, config: &CsrfConfig) -> Origin {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if header("sec-fetch-site").is_some_and(|site| site != "same-origin") {
+        return Origin::Cross;
+    }
+
+    match header(ORIGIN.as_str()) {
+        Some(origin) if origin == config.origin => Origin::Same,
+        Some(_) => Origin::Cross,
+        None if headers.contains_key("sec-fetch-site") => Origin::Same,
+        None => Origin::Unknown,
+    }
+}
+
+/// Rejects mutating requests from other origins and those lacking the token of the session.
+///
+/// Requests authenticated by a personal access token are exempt, see [`TokenAuthentication`].
+/// Sessions get their token with the first page, they can't send mutating requests before.
+pub(crate) async fn protect(
+    State(config): State<CsrfConfig>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method().is_safe() || request.extensions().get::<TokenAuthentication>().is_some() {
+        return next.run(request).await;
+    }
+
+    let origin = request_origin(request.headers(), &config);
+    if origin == Origin::Cross {
+        warn!(
+            "Rejected cross origin {} {}",
+            request.method(),
+            request.uri().path()
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let expected = match session.get::<String>(SESSION_KEY).await {
+        Ok(expected) => expected,
+        Err(err) => {
+            error!("Failed to read CSRF token from session: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let Some(expected) = expected else {
+        warn!(
+            "Rejected {} of a session without CSRF token",
+            request.uri().path()
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    // The token is either sent as header or as signal in the body, which is put back afterwards
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_BODY_SIZE).await else {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+
+    let token = parts
+        .headers
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| signal_token(&body));
+
+    // Hashes compare in constant time
+    if token.is_none_or(|token| blake3::hash(token.as_bytes()) != blake3::hash(expected.as_bytes()))
+    {
+        warn!("Rejected {} with invalid CSRF token", parts.uri.path());
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+fn signal_token(body: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<serde_json::Value>(body)
+        .ok()?
+        .get(SIGNAL)?
+    {
+        serde_json::Value::String(token) => Some(token.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        http::{HeaderValue, header::AUTHORIZATION},
+        middleware,
+        routing::post,
+    };
+    use chrono::Utc;
+    use tower::ServiceExt;
+    use tower_sessions::{MemoryStore, SessionManagerLayer};
+
+    use super::*;
+    use crate::model::{AccessToken, UserId};
+
+    const BASE_URL: &str = "https://todo.example.com";
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn config() -> CsrfConfig {
+        CsrfConfig::new(BASE_URL)
+    }
+
+    fn access_token() -> AccessToken {
+        AccessToken {
+            id: Uuid::new_v4(),
+            user_id: UserId(Uuid::new_v4()),
+            name: "CI".to_string(),
+            scopes: "write".to_string(),
+            created_at: Utc::now(),
+            expires_at: None,
+            last_used_at: None,
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    /// Sends the request through [`protect`], with [`TOKEN`] stored in the session if
+    /// `with_token` is set.
+    async fn status(request: Request, with_token: bool) -> StatusCode {
+        let router = Router::new()
+            .route(
+                "/notes",
+                post(|| async { StatusCode::OK }).get(|| async { StatusCode::OK }),
+            )
+            .layer(middleware::from_fn_with_state(config(), protect));
+
+        let router = if with_token {
+            router.layer(middleware::from_fn(
+                |session: Session, request: Request, next: Next| async move {
+                    session.insert(SESSION_KEY, TOKEN).await.unwrap();
+                    next.run(request).await
+                },
+            ))
+        } else {
+            router
+        };
+
+        router
+            .layer(SessionManagerLayer::new(MemoryStore::default()))
+            .oneshot(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    fn request(method: &str, origin: Option<&'static str>) -> Request {
+        let mut request = Request::builder().method(method).uri("/notes");
+        if let Some(origin) = origin {
+            request = request.header(ORIGIN, origin);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    fn request_with(header: Option<&'static str>, body: &'static str) -> Request {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/notes")
+            .header(ORIGIN, BASE_URL);
+        if let Some(token) = header {
+            request = request.header(HEADER, token);
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    #[test]
+    fn config_uses_the_origin_of_the_base_url() {
+        let config = CsrfConfig::new("https://todo.example.com:8443/app/");
+        assert_eq!(config.origin, "https://todo.example.com:8443");
+    }
+
+    #[test]
+    fn matching_origin_is_same() {
+        let config = config();
+        assert_eq!(
+            request_origin(&headers(&[("origin", BASE_URL)]), &config),
+            Origin::Same
+        );
+        assert_eq!(
+            request_origin(&headers(&[("sec-fetch-site", "same-origin")]), &config),
+            Origin::Same
+        );
+    }
+
+    #[test]
+    fn other_origins_are_cross() {
+        let config = config();
+        assert_eq!(
+            request_origin(&headers(&[("origin", "https://evil.example")]), &config),
+            Origin::Cross
+        );
+        assert_eq!(
+            request_origin(&headers(&[("origin", "http://todo.example.com")]), &config),
+            Origin::Cross
+        );
+        // A matching origin doesn't help if the browser says otherwise
+        assert_eq!(
+            request_origin(
+                &headers(&[("sec-fetch-site", "same-site"), ("origin", BASE_URL)]),
+                &config
+            ),
+            Origin::Cross
+        );
+    }
+
+    #[test]
+    fn missing_headers_are_unknown() {
+        assert_eq!(
+            request_origin(&HeaderMap::new(), &config()),
+            Origin::Unknown
+        );
+    }
+
+    #[tokio::test]
+    async fn safe_requests_pass() {
+        let request = request("GET", Some("https://evil.example"));
+        assert_eq!(status(request, true).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn cross_origin_requests_are_rejected() {
+        let request = request("POST", Some("https://evil.example"));
+        assert_eq!(status(request, true).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn token_authenticated_requests_are_exempt() {
+        let mut request = request("POST", Some("https://evil.example"));
+        request
+            .extensions_mut()
+            .insert(TokenAuthentication(access_token()));
+        assert_eq!(status(request, true).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn authorization_headers_alone_are_not_exempt() {
+        let mut request = request("POST", None);
+        request.headers_mut().insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Basic dXNlcjpwYXNzd29yZA=="),
+        );
+        assert_eq!(status(request, true).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn requests_of_sessions_without_token_are_rejected() {
+        let request = request("POST", Some(BASE_URL));
+        assert_eq!(status(request, false).await, StatusCode::FORBIDDEN);
+
+        let signal = request_with(None, r#"{"csrf": "0123456789abcdef"}"#);
+        assert_eq!(status(signal, false).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn requests_without_origin_pass_with_the_token() {
+        let mut request = request_with(Some(TOKEN), "");
+        request.headers_mut().remove(ORIGIN);
+        assert_eq!(status(request, true).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn requests_with_the_token_pass() {
+        let header = request_with(Some(TOKEN), "");
+        assert_eq!(status(header, true).await, StatusCode::OK);
+
+        let signal = request_with(None, r#"{"csrf": "0123456789abcdef"}"#);
+        assert_eq!(status(signal, true).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn requests_with_a_wrong_token_are_rejected() {
+        let header = request_with(Some("fedcba9876543210"), "");
+        assert_eq!(status(header, true).await, StatusCode::FORBIDDEN);
+
+        let signal = request_with(None, r#"{"csrf": "fedcba9876543210"}"#);
+        assert_eq!(status(signal, true).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn requests_without_the_token_are_rejected() {
+        let request = request_with(None, r#"{"title": "Groceries"}"#);
+        assert_eq!(status(request, true).await, StatusCode::FORBIDDEN);
+    }
+}
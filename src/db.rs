@@ -1,15 +1,52 @@
-use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+use std::{
+    collections::HashMap,
+    fs::{File, TryLockError},
+    io,
+    str::FromStr,
+    sync::Arc,
+};
+
+use sqlx::{
+    PgPool, Sqlite, SqlitePool,
+    migrate::{Migrate, MigrateDatabase, MigrateError, Migrator},
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+};
 use tracing::info;
 
-use crate::utils;
+use crate::{
+    config::DatabaseConfig,
+    repository::{NoteStorage, PostgresNoteRepository, SqliteNoteRepository},
+};
+
+/// Migrations of the `migrations` directory, embedded when the binary is compiled.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Migrations of the notes in PostgreSQL, they have their own directory as the SQL differs.
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// Opens the database and applies pending migrations.
+pub async fn create_pool(config: &DatabaseConfig) -> SqlitePool {
+    let db = connect(config).await;
 
-pub(crate) const DB_URL: &'static str = "sqlite://sqlite.db";
+    let migration_results = migrator().run(&db).await;
 
-pub async fn create_pool() -> SqlitePool {
-    if !Sqlite::database_exists(DB_URL).await.unwrap_or(false) {
-        info!("Creating database {}", DB_URL);
+    match migration_results {
+        Ok(_) => info!("Migration success"),
+        Err(error) => {
+            panic!("error: {}", error);
+        }
+    }
+
+    db
+}
 
-        match Sqlite::create_database(DB_URL).await {
+/// Opens the database without migrating it, it is created if it doesn't exist.
+pub async fn connect(config: &DatabaseConfig) -> SqlitePool {
+    let url = config.sqlite_database_url();
+    if !Sqlite::database_exists(url).await.unwrap_or(false) {
+        info!("Creating database {}", url);
+
+        match Sqlite::create_database(url).await {
             Ok(_) => info!("Creating DB was successful"),
             Err(error) => panic!("error: {}", error),
         }
@@ -17,19 +54,68 @@ pub async fn create_pool() -> SqlitePool {
         info!("Database already exists");
     }
 
-    let db = SqlitePool::connect(DB_URL).await.unwrap();
-
-    let server_dir = utils::server_directory();
-    let migrations = std::path::Path::new(&server_dir).join("./migrations");
-
-    let migration_results = sqlx::migrate::Migrator::new(migrations)
+    SqlitePoolOptions::new()
+        .min_connections(config.min_connections)
+        .max_connections(config.max_connections)
+        .connect_with(connect_options(config).unwrap())
         .await
         .unwrap()
-        .run(&db)
-        .await;
+}
 
-    match migration_results {
-        Ok(_) => info!("Migration success"),
+/// Readers don't block the writer in WAL mode, and writers wait for each other instead of
+/// failing right away.
+fn connect_options(config: &DatabaseConfig) -> Result<SqliteConnectOptions, sqlx::Error> {
+    Ok(SqliteConnectOptions::from_str(config.sqlite_database_url())?
+        .journal_mode(SqliteJournalMode::Wal)
+        // Durable enough with WAL, only a power loss may lose the last transactions
+        .synchronous(SqliteSynchronous::Normal)
+        .busy_timeout(config.busy_timeout)
+        .foreign_keys(true))
+}
+
+/// Locks the file next to the database that tells `restore` whether it is in use. Servers share
+/// the lock, `restore` takes it for itself. Returns `None` if the lock is held elsewhere, it is
+/// released when the file is dropped.
+pub fn lock_database(config: &DatabaseConfig, exclusive: bool) -> io::Result<Option<File>> {
+    let options = SqliteConnectOptions::from_str(config.sqlite_database_url())
+        .map_err(io::Error::other)?;
+    let mut path = options.get_filename().as_os_str().to_owned();
+    path.push(".lock");
+
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let result = if exclusive {
+        file.try_lock()
+    } else {
+        file.try_lock_shared()
+    };
+
+    match result {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(error)) => Err(error),
+    }
+}
+
+pub fn migrator() -> &'static Migrator {
+    &MIGRATOR
+}
+
+/// Whether the URL points to PostgreSQL instead of a SQLite file.
+pub fn is_postgres(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+/// Connects to PostgreSQL and applies pending migrations, the database must exist.
+pub async fn create_postgres_pool(url: &str) -> PgPool {
+    let db = PgPool::connect(url).await.unwrap();
+
+    match POSTGRES_MIGRATOR.run(&db).await {
+        Ok(_) => info!("PostgreSQL migration success"),
         Err(error) => {
             panic!("error: {}", error);
         }
@@ -37,3 +123,77 @@ pub async fn create_pool() -> SqlitePool {
 
     db
 }
+
+/// Storage of the notes, chosen by the scheme of `database.url`.
+pub(crate) async fn note_storage(config: &DatabaseConfig, db: SqlitePool) -> Arc<dyn NoteStorage> {
+    match config.postgres_notes_url() {
+        Some(url) => {
+            info!("Storing the notes in PostgreSQL");
+            Arc::new(PostgresNoteRepository::new(create_postgres_pool(url).await))
+        }
+        None => Arc::new(SqliteNoteRepository::new(db)),
+    }
+}
+
+/// State of a migration in the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    /// The migration file differs from the one that was applied.
+    Changed,
+    Pending,
+}
+
+impl std::fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Applied => "applied",
+            Self::Changed => "changed after it was applied",
+            Self::Pending => "pending",
+        })
+    }
+}
+
+/// Version, description and state of every migration.
+pub async fn migration_status(
+    db: &SqlitePool,
+) -> Result<Vec<(i64, String, MigrationState)>, MigrateError> {
+    let mut connection = db.acquire().await?;
+    status(&MIGRATOR, &mut *connection).await
+}
+
+/// Version, description and state of every migration of the notes in PostgreSQL.
+pub async fn postgres_migration_status(
+    db: &PgPool,
+) -> Result<Vec<(i64, String, MigrationState)>, MigrateError> {
+    let mut connection = db.acquire().await?;
+    status(&POSTGRES_MIGRATOR, &mut *connection).await
+}
+
+async fn status(
+    migrator: &Migrator,
+    connection: &mut impl Migrate,
+) -> Result<Vec<(i64, String, MigrationState)>, MigrateError> {
+    connection.ensure_migrations_table().await?;
+
+    let applied: HashMap<_, _> = connection
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect();
+
+    Ok(migrator
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .map(|migration| {
+            let state = match applied.get(&migration.version) {
+                Some(checksum) if *checksum == migration.checksum => MigrationState::Applied,
+                Some(_) => MigrationState::Changed,
+                None => MigrationState::Pending,
+            };
+
+            (migration.version, migration.description.to_string(), state)
+        })
+        .collect())
+}
@@ -0,0 +1,47 @@
+use askama::Template;
+use datastar::{consts::FragmentMergeMode, prelude::MergeFragments};
+use uuid::Uuid;
+
+use crate::model;
+
+pub(crate) const ACCESS_TOKEN_LIST_ID: &str = "#access-token-list";
+pub(crate) const NEW_ACCESS_TOKEN_ID: &str = "#new-access-token";
+
+#[inline(always)]
+pub(crate) fn access_token_selector(id: &Uuid) -> String {
+    format!("#access-token-{}", id)
+}
+
+#[derive(Template)]
+#[template(path = "fragments/access-token.fragment.html")]
+pub(crate) struct AccessTokenFragment {
+    pub token: model::AccessToken,
+}
+
+impl AccessTokenFragment {
+    pub(crate) fn fragment(&self) -> Result<MergeFragments, askama::Error> {
+        self.render().map(|html| {
+            MergeFragments::new(html)
+                .selector(ACCESS_TOKEN_LIST_ID)
+                .merge_mode(FragmentMergeMode::Append)
+        })
+    }
+}
+
+/// Shows the plain text value of a newly created token, it can't be retrieved later.
+#[derive(Template)]
+#[template(path = "fragments/new-access-token.fragment.html")]
+pub(crate) struct NewAccessTokenFragment {
+    pub name: String,
+    pub secret: String,
+}
+
+impl NewAccessTokenFragment {
+    pub(crate) fn fragment(&self) -> Result<MergeFragments, askama::Error> {
+        self.render().map(|html| {
+            MergeFragments::new(html)
+                .selector(NEW_ACCESS_TOKEN_ID)
+                .merge_mode(FragmentMergeMode::Outer)
+        })
+    }
+}
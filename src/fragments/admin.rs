@@ -0,0 +1,32 @@
+use askama::Template;
+use datastar::{consts::FragmentMergeMode, prelude::MergeFragments};
+
+use crate::model::{UserId, UserOverview};
+
+pub(crate) const CSP_REPORTS_SELECTOR: &str = "#csp-reports .csp-report";
+
+#[inline(always)]
+pub(crate) fn admin_user_selector(id: &UserId) -> String {
+    format!("#admin-user-{}", id)
+}
+
+/// Row of the user table in the admin console.
+#[derive(Template)]
+#[template(path = "fragments/admin-user.fragment.html")]
+pub(crate) struct AdminUserFragment {
+    pub user: UserOverview,
+    /// The user is in the cache of authenticated users.
+    pub signed_in: bool,
+    /// The row shows the administrator themselves, who can't sign out or disable themselves.
+    pub current: bool,
+}
+
+impl AdminUserFragment {
+    pub(crate) fn fragment(&self) -> Result<MergeFragments, askama::Error> {
+        self.render().map(|html| {
+            MergeFragments::new(html)
+                .selector(admin_user_selector(&self.user.id))
+                .merge_mode(FragmentMergeMode::Outer)
+        })
+    }
+}
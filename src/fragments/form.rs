@@ -0,0 +1,37 @@
+use askama::Template;
+use datastar::{consts::FragmentMergeMode, prelude::MergeFragments};
+
+/// Message shown below a form, replaces the element with the same id.
+#[derive(Template)]
+#[template(path = "fragments/form-message.fragment.html")]
+pub(crate) struct FormMessageFragment {
+    pub id: &'static str,
+    pub message: String,
+    pub error: bool,
+}
+
+impl FormMessageFragment {
+    pub(crate) fn error(id: &'static str, message: impl ToString) -> Self {
+        Self {
+            id,
+            message: message.to_string(),
+            error: true,
+        }
+    }
+
+    pub(crate) fn success(id: &'static str, message: impl ToString) -> Self {
+        Self {
+            id,
+            message: message.to_string(),
+            error: false,
+        }
+    }
+
+    pub(crate) fn fragment(&self) -> Result<MergeFragments, askama::Error> {
+        self.render().map(|html| {
+            MergeFragments::new(html)
+                .selector(format!("#{}", self.id))
+                .merge_mode(FragmentMergeMode::Outer)
+        })
+    }
+}
@@ -1,3 +1,15 @@
+mod access_token;
+mod admin;
+mod form;
 mod note;
+mod reconnect;
+mod session;
+mod toast;
 
+pub(crate) use access_token::*;
+pub(crate) use admin::*;
+pub(crate) use form::*;
 pub(crate) use note::*;
+pub(crate) use reconnect::*;
+pub(crate) use session::*;
+pub(crate) use toast::*;
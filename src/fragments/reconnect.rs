@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use askama::Template;
+use datastar::{consts::FragmentMergeMode, prelude::MergeFragments};
+
+/// Notice of a server shutdown, the page reloads after the delay to reach another instance.
+#[derive(Debug, Clone, Template)]
+#[template(path = "fragments/reconnect.fragment.html")]
+pub(crate) struct ReconnectFragment {
+    pub delay: Duration,
+}
+
+impl ReconnectFragment {
+    pub(crate) fn fragment(&self) -> Result<MergeFragments, askama::Error> {
+        self.render().map(|html| {
+            MergeFragments::new(html)
+                .selector("body")
+                .merge_mode(FragmentMergeMode::Append)
+        })
+    }
+}
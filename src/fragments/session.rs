@@ -0,0 +1,6 @@
+pub(crate) const OTHER_SESSIONS_SELECTOR: &str = "#session-list .session:not(.session--current)";
+
+#[inline(always)]
+pub(crate) fn session_selector(id: &str) -> String {
+    format!("#session-{}", id)
+}
@@ -0,0 +1,36 @@
+use askama::Template;
+use datastar::{consts::FragmentMergeMode, prelude::MergeFragments};
+use serde::{Deserialize, Serialize};
+
+/// Short notice at the bottom of the page, it fades out on its own.
+#[derive(Debug, Clone, Template, Deserialize, Serialize)]
+#[template(path = "fragments/toast.fragment.html")]
+pub(crate) struct ToastFragment {
+    pub message: String,
+    pub error: bool,
+}
+
+impl ToastFragment {
+    pub(crate) fn error(message: impl ToString) -> Self {
+        Self {
+            message: message.to_string(),
+            error: true,
+        }
+    }
+
+    pub(crate) fn success(message: impl ToString) -> Self {
+        Self {
+            message: message.to_string(),
+            error: false,
+        }
+    }
+
+    /// Shows the toast on the current page, e.g. in response to a Datastar request.
+    pub(crate) fn fragment(&self) -> Result<MergeFragments, askama::Error> {
+        self.render().map(|html| {
+            MergeFragments::new(html)
+                .selector("body")
+                .merge_mode(FragmentMergeMode::Append)
+        })
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{
+        HeaderName, HeaderValue, StatusCode,
+        header::{
+            CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY_REPORT_ONLY, REFERRER_POLICY,
+            STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+        },
+        request::Parts,
+    },
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use uuid::Uuid;
+
+/// Policy that applies unless a directive is overridden in the configuration.
+const DEFAULT_CSP: &[(&str, &str)] = &[
+    ("default-src", "'self'"),
+    ("base-uri", "'none'"),
+    ("object-src", "'none'"),
+    ("script-src", "'self'"),
+    ("style-src", "'self'"),
+    ("img-src", "'self' data:"),
+    ("frame-ancestors", "'none'"),
+    ("form-action", "'self'"),
+    ("report-uri", "/csp-report"),
+    ("report-to", "csp-endpoint"),
+];
+
+/// Endpoint of the Reporting API, browsers without it use `report-uri`.
+const REPORTING_ENDPOINTS: &str = r#"csp-endpoint="/csp-report""#;
+
+/// Content Security Policy and further security headers.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct HeadersConfig {
+    /// Directives that replace or add to the default policy, e.g.
+    /// `img-src = "'self' https:"`.
+    pub csp_directives: BTreeMap<String, String>,
+    /// Violations are only reported, used to roll out a changed policy without breaking pages.
+    pub csp_report_only: bool,
+    /// Allow `'unsafe-eval'` scripts, Datastar evaluates its expressions with it.
+    pub csp_unsafe_eval: bool,
+    /// Allow `'unsafe-inline'` styles.
+    pub csp_unsafe_inline_styles: bool,
+    /// The following replace the value of the header, an empty value disables it. HSTS is only
+    /// sent by default if the application uses HTTPS.
+    pub strict_transport_security: Option<String>,
+    pub x_frame_options: Option<String>,
+    pub x_content_type_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub permissions_policy: Option<String>,
+    pub cross_origin_opener_policy: Option<String>,
+}
+
+/// Security headers sent with every response.
+#[derive(Debug, Clone)]
+pub(crate) struct SecurityHeaders {
+    /// Directives of the Content Security Policy, script and style sources get the nonce of
+    /// the request appended.
+    csp: Vec<(String, String)>,
+    csp_report_only: bool,
+    /// Further headers with the same value for every response.
+    fixed: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl SecurityHeaders {
+    pub fn new(config: &HeadersConfig, https: bool) -> Self {
+        let mut csp: Vec<(String, String)> = DEFAULT_CSP
+            .iter()
+            .map(|(directive, sources)| (directive.to_string(), sources.to_string()))
+            .collect();
+
+        for (name, sources) in &config.csp_directives {
+            match csp.iter_mut().find(|(existing, _)| existing == name) {
+                Some((_, existing)) => existing.clone_from(sources),
+                None => csp.push((name.clone(), sources.clone())),
+            }
+        }
+
+        let mut allow = |directive: &str, source: &str| {
+            if let Some((_, sources)) = csp.iter_mut().find(|(name, _)| name == directive) {
+                sources.push(' ');
+                sources.push_str(source);
+            }
+        };
+        if config.csp_unsafe_eval {
+            allow("script-src", "'unsafe-eval'");
+        }
+        if config.csp_unsafe_inline_styles {
+            allow("style-src", "'unsafe-inline'");
+        }
+
+        // HSTS would lock browsers out of a deployment without TLS
+        let hsts = if https { "max-age=31536000" } else { "" };
+
+        let mut fixed: Vec<(HeaderName, HeaderValue)> = [
+            (
+                STRICT_TRANSPORT_SECURITY,
+                &config.strict_transport_security,
+                hsts,
+            ),
+            (X_FRAME_OPTIONS, &config.x_frame_options, "deny"),
+            (
+                X_CONTENT_TYPE_OPTIONS,
+                &config.x_content_type_options,
+                "nosniff",
+            ),
+            (REFERRER_POLICY, &config.referrer_policy, "same-origin"),
+            (
+                HeaderName::from_static("permissions-policy"),
+                &config.permissions_policy,
+                "camera=(), microphone=(), geolocation=(), payment=(), usb=()",
+            ),
+            (
+                HeaderName::from_static("cross-origin-opener-policy"),
+                &config.cross_origin_opener_policy,
+                "same-origin",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(header, value, default)| {
+            let value = value.as_deref().unwrap_or(default);
+            if value.is_empty() {
+                return None;
+            }
+
+            // Validated with the configuration
+            Some((header, HeaderValue::from_str(value).ok()?))
+        })
+        .collect();
+        fixed.push((
+            HeaderName::from_static("reporting-endpoints"),
+            HeaderValue::from_static(REPORTING_ENDPOINTS),
+        ));
+
+        Self {
+            csp,
+            csp_report_only: config.csp_report_only,
+            fixed,
+        }
+    }
+
+    fn content_security_policy(&self, nonce: &str) -> String {
+        self.csp
+            .iter()
+            .map(|(directive, sources)| match directive.as_str() {
+                // Browsers ignore 'unsafe-inline' if a nonce is present
+                "script-src" | "style-src" if !sources.contains("'unsafe-inline'") => {
+                    format!("{directive} {sources} 'nonce-{nonce}'")
+                }
+                _ => format!("{directive} {sources}"),
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Nonce of the request, templates put it on inline scripts and styles.
+#[derive(Debug, Clone)]
+pub(crate) struct CspNonce(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for CspNonce {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<CspNonce>().cloned().ok_or_else(|| {
+            error!("No CSP nonce, is the security headers middleware missing?");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+    }
+}
+
+/// Adds the security headers to responses that don't set them already.
+pub(crate) async fn security_headers(
+    State(config): State<Arc<SecurityHeaders>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let nonce = Uuid::new_v4().simple().to_string();
+    request.extensions_mut().insert(CspNonce(nonce.clone()));
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    let csp_header = if config.csp_report_only {
+        CONTENT_SECURITY_POLICY_REPORT_ONLY
+    } else {
+        CONTENT_SECURITY_POLICY
+    };
+    if let Ok(value) = HeaderValue::from_str(&config.content_security_policy(&nonce)) {
+        headers.entry(csp_header).or_insert(value);
+    }
+
+    for (header, value) in &config.fixed {
+        headers.entry(header).or_insert_with(|| value.clone());
+    }
+
+    response
+}
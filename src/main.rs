@@ -1,27 +1,81 @@
-use ::tracing::info;
+use std::{process::ExitCode, sync::Arc};
+
+use ::tracing::{error, info};
+use clap::Parser;
+use datastar_axum_todolist::{
+    cli::{self, Cli, Command},
+    config::Config,
+    db, routes, server,
+    state::AppState,
+    tracing,
+};
 use dotenv::dotenv;
-use datastar_axum_todolist::{db, routes, state::AppState, tracing, utils};
 
 #[tokio::main]
-async fn main() {
-    tracing::init_tracing();
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let _ = dotenv().ok();
+
+    // Logging is configured by the configuration, so its errors are printed directly
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml());
+        return ExitCode::SUCCESS;
+    }
 
+    let _tracing = tracing::init_tracing(&config);
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(Arc::new(config)).await,
+        command => cli::run(command, &config).await,
+    }
+}
+
+async fn serve(config: Arc<Config>) -> ExitCode {
     info!("Starting server...");
-    info!("  Server directory: {}", utils::server_directory());
     info!("  Version: {}", env!("CARGO_PKG_VERSION"));
 
-    let _ = dotenv().ok();
+    // Keeps `restore` from replacing the database while it is in use
+    let _lock = match db::lock_database(&config.database, false) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            error!("The database is being restored");
+            return ExitCode::FAILURE;
+        }
+        Err(err) => {
+            error!("Failed to lock the database: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let database = db::create_pool(&config.database).await;
+    let app_state = AppState::new(config.clone(), database.clone()).await;
 
-    let database = db::create_pool().await;
-    let app_state = AppState::from_database(database).await;
+    let shutdown = app_state.shutdown().clone();
+    tokio::spawn(shutdown.clone().listen(config.server.shutdown_timeout));
 
-    let router = routes::router().await.with_state(app_state);
+    let router = routes::router(&app_state).await.with_state(app_state);
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
+    let result = server::serve(&config.server, router, shutdown).await;
 
-    info!("Listening on http://{}", listener.local_addr().unwrap());
+    database.close().await;
 
-    axum::serve(listener, router).await.unwrap();
+    match result {
+        Ok(()) => {
+            info!("Shutdown complete");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            error!("Failed to serve: {}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,159 @@
+use std::{net::IpAddr, sync::Arc, time::Instant};
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request},
+    http::{
+        HeaderMap,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+    },
+    middleware::Next,
+    response::Response,
+};
+use http_body_util::BodyExt;
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+
+use crate::service::OidcAuthBackend;
+
+/// Buckets of the latency histograms, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Route label of requests that matched no route, e.g. static files, so unknown paths don't
+/// create new series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Access to `/metrics` is granted with the bearer token or from one of the allowed addresses.
+///
+/// Behind a reverse proxy on the same host every request comes from a loopback address, so
+/// allowing those would make the metrics public. They are disabled until configured.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct MetricsConfig {
+    pub enabled: bool,
+    pub token: Option<String>,
+    pub allowed_addresses: Vec<IpAddr>,
+}
+
+/// Prometheus recorder of the application metrics. Most metrics are recorded where they
+/// happen, the gauges of the caches and the pool are sampled when they are scraped.
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    handle: PrometheusHandle,
+    config: Arc<MetricsConfig>,
+    db: Pool<Sqlite>,
+    auth: OidcAuthBackend,
+}
+
+impl Metrics {
+    /// Installs the global recorder, it must only be called once.
+    pub fn install(config: MetricsConfig, db: Pool<Sqlite>, auth: OidcAuthBackend) -> Self {
+        let handle = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)
+            .expect("The latency buckets are not empty")
+            .install_recorder()
+            .expect("Failed to install the metrics recorder");
+
+        Self {
+            handle,
+            config: Arc::new(config),
+            db,
+            auth,
+        }
+    }
+
+    /// Whether the client may read the metrics.
+    pub fn allows(&self, ip: Option<&str>, headers: &HeaderMap) -> bool {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        // Hashes are compared in constant time
+        let valid_token = match (&self.config.token, token) {
+            (Some(expected), Some(token)) => {
+                blake3::hash(expected.as_bytes()) == blake3::hash(token.as_bytes())
+            }
+            _ => false,
+        };
+        if valid_token {
+            return true;
+        }
+
+        ip.and_then(|ip| ip.parse::<IpAddr>().ok())
+            .map(|ip| ip.to_canonical())
+            .is_some_and(|ip| self.config.allowed_addresses.contains(&ip))
+    }
+
+    /// Samples the gauges and renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        gauge!("users_cache_entries").set(self.auth.cached_users() as f64);
+
+        let size = self.db.size();
+        let idle = self.db.num_idle() as u32;
+        gauge!("db_pool_connections", "state" => "idle").set(f64::from(idle));
+        gauge!("db_pool_connections", "state" => "active")
+            .set(f64::from(size.saturating_sub(idle)));
+        gauge!("db_pool_max_connections").set(f64::from(self.db.options().get_max_connections()));
+
+        self.handle.run_upkeep();
+        self.handle.render()
+    }
+}
+
+/// Counts the requests and their latency per matched route, and tracks the open SSE streams.
+pub(crate) async fn track_requests(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    counter!("http_requests_total", &labels).increment(1);
+    histogram!("http_request_duration_seconds", &labels).record(start.elapsed().as_secs_f64());
+
+    let is_stream = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"));
+    if !is_stream {
+        return response;
+    }
+
+    // The stream is open until its body is dropped
+    let stream = OpenStream::new();
+    response.map(|body| {
+        Body::new(body.map_frame(move |frame| {
+            let _stream = &stream;
+            frame
+        }))
+    })
+}
+
+/// Counts an SSE stream as open while it lives.
+struct OpenStream;
+
+impl OpenStream {
+    fn new() -> Self {
+        gauge!("sse_streams_open").increment(1.0);
+        Self
+    }
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        gauge!("sse_streams_open").decrement(1.0);
+    }
+}
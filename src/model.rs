@@ -1,5 +1,7 @@
 use std::{
+    collections::HashSet,
     fmt::{Debug, Display},
+    str::FromStr,
     time::Duration,
 };
 
@@ -11,6 +13,9 @@ use sqlx::FromRow;
 use uuid::Uuid;
 
 pub(crate) type NoteId = Uuid;
+pub(crate) type AccessTokenId = Uuid;
+/// Identifies a single sign-in, every device of a user signs in separately.
+pub(crate) type LoginId = Uuid;
 
 #[derive(Debug, Clone, FromRow)]
 pub struct Note {
@@ -20,6 +25,23 @@ pub struct Note {
     pub checked: bool,
 }
 
+/// A note together with the time it was moved to the trash, as copied between databases.
+#[derive(Debug, Clone, FromRow)]
+pub struct StoredNote {
+    #[sqlx(flatten)]
+    pub note: Note,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<Note> for StoredNote {
+    fn from(note: Note) -> Self {
+        Self {
+            note,
+            deleted_at: None,
+        }
+    }
+}
+
 #[derive(
     Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd, Hash, Serialize, Deserialize, sqlx::Type,
 )]
@@ -32,33 +54,395 @@ impl Display for UserId {
     }
 }
 
-#[derive(Debug, Clone, FromRow)]
+#[derive(Clone, FromRow)]
 pub struct User {
     pub id: UserId,
     pub username: String,
-    pub password: String,
+    /// Argon2 hash of the password, only set for local accounts.
+    pub password: Option<String>,
+    /// Disabled by an administrator, the user can't sign in.
+    pub disabled: bool,
+}
+
+impl Debug for User {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("disabled", &self.disabled)
+            .finish()
+    }
+}
+
+/// A user together with its usage, as listed in the admin console.
+#[derive(Debug, Clone, FromRow)]
+pub struct UserOverview {
+    pub id: UserId,
+    pub username: String,
+    /// The user has a password and can sign in without an OIDC provider.
+    pub local: bool,
+    pub disabled: bool,
+    /// Counted in the note storage, which may be another database.
+    #[sqlx(skip)]
+    pub note_count: i64,
+    pub access_token_count: i64,
+    pub session_count: i64,
+}
+
+/// An account at an OIDC provider that is linked to a [`User`].
+#[derive(Debug, Clone, FromRow)]
+pub struct UserIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub user_id: UserId,
+}
+
+/// Permission granted to a personal access token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenScope {
+    /// Read notes.
+    Read,
+    /// Create, change and delete notes, implies [`TokenScope::Read`].
+    Write,
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Write => "write",
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(TokenScope::Read),
+            "write" => Ok(TokenScope::Write),
+            _ => Err(format!("unknown scope {s}")),
+        }
+    }
+}
+
+/// Application role, granted by the groups or roles claim of an OIDC provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Manage users and sessions.
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role {s}")),
+        }
+    }
+}
+
+/// Kind of a security relevant event in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEvent {
+    LoginSucceeded,
+    /// The reason is kept in the details of the entry.
+    LoginFailed,
+    Logout,
+    /// A session was signed out by the application, e.g. because the provider revoked the
+    /// access token.
+    SessionInvalidated,
+    PasswordChanged,
+    TokenCreated,
+    TokenRevoked,
+    PermissionDenied,
+    UserDisabled,
+    UserEnabled,
+    /// Many records were deleted at once, e.g. all other sessions of a user.
+    BulkDeletion,
+}
+
+impl AuditEvent {
+    pub const ALL: [AuditEvent; 11] = [
+        AuditEvent::LoginSucceeded,
+        AuditEvent::LoginFailed,
+        AuditEvent::Logout,
+        AuditEvent::SessionInvalidated,
+        AuditEvent::PasswordChanged,
+        AuditEvent::TokenCreated,
+        AuditEvent::TokenRevoked,
+        AuditEvent::PermissionDenied,
+        AuditEvent::UserDisabled,
+        AuditEvent::UserEnabled,
+        AuditEvent::BulkDeletion,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::LoginSucceeded => "login_succeeded",
+            AuditEvent::LoginFailed => "login_failed",
+            AuditEvent::Logout => "logout",
+            AuditEvent::SessionInvalidated => "session_invalidated",
+            AuditEvent::PasswordChanged => "password_changed",
+            AuditEvent::TokenCreated => "token_created",
+            AuditEvent::TokenRevoked => "token_revoked",
+            AuditEvent::PermissionDenied => "permission_denied",
+            AuditEvent::UserDisabled => "user_disabled",
+            AuditEvent::UserEnabled => "user_enabled",
+            AuditEvent::BulkDeletion => "bulk_deletion",
+        }
+    }
+}
+
+impl FromStr for AuditEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditEvent::ALL
+            .into_iter()
+            .find(|event| event.as_str() == s)
+            .ok_or_else(|| format!("unknown audit event {s}"))
+    }
+}
+
+/// An event that is about to be appended to the audit log.
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub event: AuditEvent,
+    /// The user the event is about.
+    pub user_id: Option<UserId>,
+    /// The user that caused the event, if it isn't the user itself, e.g. an administrator.
+    pub actor_id: Option<UserId>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: String,
+}
+
+impl AuditRecord {
+    pub fn new(event: AuditEvent) -> Self {
+        Self {
+            event,
+            user_id: None,
+            actor_id: None,
+            ip: None,
+            user_agent: None,
+            details: String::new(),
+        }
+    }
+
+    pub fn user(mut self, user_id: UserId) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn actor(mut self, actor_id: UserId) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn client(mut self, ip: Option<String>, user_agent: Option<String>) -> Self {
+        self.ip = ip;
+        self.user_agent = user_agent;
+        self
+    }
+
+    pub fn details(mut self, details: impl ToString) -> Self {
+        self.details = details.to_string();
+        self
+    }
+}
+
+/// An entry of the audit log, exported as JSON line.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    /// [`AuditEvent`] as string.
+    pub event: String,
+    pub user_id: Option<UserId>,
+    pub actor_id: Option<UserId>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: String,
 }
 
+/// Restricts the audit log entries that are listed or exported, unset fields match all.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub event: Option<AuditEvent>,
+    /// Matches the user as well as the actor of an entry.
+    pub user_id: Option<UserId>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Personal access token, the token itself is only stored as hash.
+#[derive(Debug, Clone, FromRow)]
+pub struct AccessToken {
+    pub id: AccessTokenId,
+    pub user_id: UserId,
+    pub name: String,
+    /// Space separated list of [`TokenScope`]s.
+    pub scopes: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl AccessToken {
+    pub fn allows(&self, scope: TokenScope) -> bool {
+        self.scopes
+            .split_whitespace()
+            .filter_map(|scope| scope.parse::<TokenScope>().ok())
+            .any(|granted| granted == scope || granted == TokenScope::Write)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// The device a session was signed in from, kept in the session itself.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SessionDevice {
+    pub user_id: UserId,
+    pub login_id: LoginId,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub logged_in_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+impl SessionDevice {
+    /// Key of the device in the session data.
+    pub const SESSION_KEY: &'static str = "device";
+}
+
+/// A signed in session of a user, as listed on the sessions page.
 #[derive(Debug, Clone)]
-pub enum PendingAction {
-    CheckNote(NoteId),
-    UncheckNote(NoteId),
-    EditNote(NoteId, String),
-    DeleteNote(NoteId),
-    CreateNote(String),
+pub struct ActiveSession {
+    pub id: String,
+    pub device: SessionDevice,
+    pub expires_at: DateTime<Utc>,
 }
 
+/// A Content Security Policy violation as reported by a browser.
+#[derive(Debug, Clone)]
+pub struct CspViolation {
+    pub document_uri: String,
+    /// The effective directive that was violated, e.g. `script-src-elem`.
+    pub directive: String,
+    pub blocked_uri: String,
+    pub source_file: String,
+    pub line_number: i64,
+    /// `enforce` or `report`, the latter for `Content-Security-Policy-Report-Only`.
+    pub disposition: String,
+    pub sample: Option<String>,
+}
+
+/// A stored [`CspViolation`], identical violations are counted instead of stored again.
+#[derive(Debug, Clone, FromRow)]
+pub struct CspReport {
+    pub id: i64,
+    pub document_uri: String,
+    pub directive: String,
+    pub blocked_uri: String,
+    pub source_file: String,
+    pub line_number: i64,
+    pub disposition: String,
+    pub sample: Option<String>,
+    /// User agent of the most recent report.
+    pub user_agent: Option<String>,
+    pub count: i64,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// A Datastar request that was interrupted by a login, it is replayed once the user signed in.
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct ApplicationSignals {
-    pub note: String,
+pub struct PendingAction {
+    pub method: String,
+    /// Path of the request, relative to the application.
+    pub path: String,
+    /// Signals that were sent with the request.
+    pub signals: serde_json::Value,
+}
+
+/// Determines how the access token of a [`SessionUser`] is re-validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AccessTokenKind {
+    /// Signed JWT, validated locally against the JWKS of the provider.
+    Jwt,
+    /// Opaque token, validated via the introspection endpoint of the provider.
+    Opaque,
+}
+
+/// How the user of a session signed in.
+#[derive(Clone, Deserialize, Serialize)]
+pub enum SessionCredentials {
+    /// Signed in at an OIDC provider, the access token is re-validated periodically.
+    Oidc {
+        provider: String,
+        access_token: String,
+        access_token_kind: AccessTokenKind,
+    },
+    /// Signed in with the username and password of a local account.
+    Password,
+}
+
+impl Debug for SessionCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Oidc {
+                provider,
+                access_token_kind,
+                ..
+            } => f
+                .debug_struct("Oidc")
+                .field("provider", provider)
+                .field("access_token", &"<redacted>")
+                .field("access_token_kind", access_token_kind)
+                .finish(),
+            Self::Password => f.write_str("Password"),
+        }
+    }
+}
+
+/// Id of a [`SessionUser`], which is kept per sign-in so every session has its own access token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SessionUserId {
+    pub user_id: UserId,
+    pub login_id: LoginId,
+}
+
+impl Display for SessionUserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.user_id, self.login_id)
+    }
 }
 
 #[derive(Clone)]
 pub struct SessionUser {
     pub id: UserId,
-    pub access_token: String,
-    pub access_token_hash: Vec<u8>,
+    pub login_id: LoginId,
+    pub credentials: SessionCredentials,
+    /// Hash of the access token or password hash, changing it invalidates the session.
+    pub auth_hash: Vec<u8>,
     pub pending_action: Option<PendingAction>,
+    pub roles: HashSet<Role>,
     pub expiration: Duration,
     pub last_health_check: DateTime<Utc>,
 }
@@ -67,19 +451,30 @@ impl Debug for SessionUser {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SessionUser")
             .field("id", &self.id)
-            .field("access_token", &"<redacted>")
-            .field("access_token_hash", &"<redacted>")
+            .field("login_id", &self.login_id)
+            .field("credentials", &self.credentials)
+            .field("auth_hash", &"<redacted>")
             .field("pending_action", &self.pending_action)
+            .field("roles", &self.roles)
             .field("expiration", &self.expiration)
             .field("last_health_check", &self.last_health_check)
             .finish()
     }
 }
 
-impl Expiry<UserId, SessionUser> for SessionUser {
+impl SessionUser {
+    pub fn key(&self) -> SessionUserId {
+        SessionUserId {
+            user_id: self.id,
+            login_id: self.login_id,
+        }
+    }
+}
+
+impl Expiry<SessionUserId, SessionUser> for SessionUser {
     fn expire_after_create(
         &self,
-        _: &UserId,
+        _: &SessionUserId,
         value: &SessionUser,
         _: std::time::Instant,
     ) -> Option<Duration> {
@@ -88,13 +483,62 @@ impl Expiry<UserId, SessionUser> for SessionUser {
 }
 
 impl AuthUser for SessionUser {
-    type Id = UserId;
+    type Id = SessionUserId;
 
     fn id(&self) -> Self::Id {
-        self.id
+        self.key()
     }
 
     fn session_auth_hash(&self) -> &[u8] {
-        &self.access_token_hash
+        &self.auth_hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn token(scopes: &str, expires_at: Option<DateTime<Utc>>) -> AccessToken {
+        AccessToken {
+            id: Uuid::new_v4(),
+            user_id: UserId(Uuid::new_v4()),
+            name: "CI".to_string(),
+            scopes: scopes.to_string(),
+            created_at: Utc::now(),
+            expires_at,
+            last_used_at: None,
+        }
+    }
+
+    #[test]
+    fn read_scope_only_allows_reading() {
+        let token = token("read", None);
+        assert!(token.allows(TokenScope::Read));
+        assert!(!token.allows(TokenScope::Write));
+    }
+
+    #[test]
+    fn write_scope_implies_read() {
+        let token = token("write", None);
+        assert!(token.allows(TokenScope::Read));
+        assert!(token.allows(TokenScope::Write));
+    }
+
+    #[test]
+    fn unknown_scopes_allow_nothing() {
+        let token = token("admin  delete", None);
+        assert!(!token.allows(TokenScope::Read));
+        assert!(!token.allows(TokenScope::Write));
+    }
+
+    #[test]
+    fn expires_at_the_expiry_date() {
+        let now = Utc::now();
+        assert!(!token("read", None).is_expired(now));
+        assert!(!token("read", Some(now + TimeDelta::seconds(1))).is_expired(now));
+        assert!(token("read", Some(now)).is_expired(now));
+        assert!(token("read", Some(now - TimeDelta::days(1))).is_expired(now));
     }
 }
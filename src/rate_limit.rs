@@ -0,0 +1,327 @@
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_stream::stream;
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use datastar::Sse;
+use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    auth::{AuthSession, ClientInfo, CurrentUser},
+    fragments::ToastFragment,
+};
+
+/// Requests a client may send, refilled continuously up to the burst size. A limit of 0
+/// requests per minute disables rate limiting.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RateLimit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+/// Signing in is limited per client, changes are limited per user.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RateLimits {
+    pub auth: RateLimit,
+    /// Signing in of all clients without a known address, they share one bucket.
+    pub unknown_clients: RateLimit,
+    pub user: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            auth: RateLimit {
+                per_minute: 20,
+                burst: 10,
+            },
+            unknown_clients: RateLimit {
+                per_minute: 60,
+                burst: 20,
+            },
+            user: RateLimit {
+                per_minute: 120,
+                burst: 30,
+            },
+        }
+    }
+}
+
+impl RateLimit {
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket rate limiter, one bucket per key.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Cache<String, Arc<Mutex<Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(name: &'static str, mut limit: RateLimit) -> Self {
+        limit.burst = limit.burst.max(1);
+
+        // An idle bucket is full again after this long, so it can be dropped
+        let refill = f64::from(limit.burst) / limit.per_second().max(1e-3);
+
+        Self {
+            limit,
+            buckets: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_idle(Duration::from_secs_f64(refill))
+                .name(name)
+                .build(),
+        }
+    }
+
+    /// Takes a token from the bucket of `key`, or returns how long to wait for the next one.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        if self.limit.per_minute == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let bucket = self.buckets.get_with_by_ref(key, || {
+            Arc::new(Mutex::new(Bucket {
+                tokens: f64::from(self.limit.burst),
+                updated_at: now,
+            }))
+        });
+        let mut bucket = bucket.lock().unwrap();
+
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.limit.per_second())
+            .min(f64::from(self.limit.burst));
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.limit.per_second(),
+            ))
+        }
+    }
+}
+
+/// Rate limiter per client address. The addresses of an IPv6 network share a bucket, as
+/// clients usually get a whole /64. Clients without a known address, e.g. behind a proxy that
+/// isn't trusted, share one bucket with a limit of its own.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientRateLimiter {
+    clients: RateLimiter,
+    unknown: RateLimiter,
+}
+
+impl ClientRateLimiter {
+    pub fn new(name: &'static str, limit: RateLimit, unknown: RateLimit) -> Self {
+        Self {
+            clients: RateLimiter::new(name, limit),
+            unknown: RateLimiter::new(name, unknown),
+        }
+    }
+
+    /// Takes a token from the bucket of the client, or returns how long to wait for the next one.
+    pub fn check(&self, ip: Option<IpAddr>) -> Result<(), Duration> {
+        match ip {
+            Some(ip) => self.clients.check(&client_key(ip)),
+            None => self.unknown.check(""),
+        }
+    }
+}
+
+/// Key of the bucket of a client, the /64 network for IPv6 addresses.
+fn client_key(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let network = u128::from(ip) & !u128::from(u64::MAX);
+            format!("{}/64", Ipv6Addr::from(network))
+        }
+    }
+}
+
+/// Limits all requests per client IP, for the routes that sign users in.
+pub(crate) async fn limit_by_ip(
+    State(limiter): State<ClientRateLimiter>,
+    client: ClientInfo,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = client.ip_addr();
+
+    match limiter.check(ip) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            warn!(
+                "Rate limited {} {} of {}",
+                request.method(),
+                request.uri().path(),
+                ip.map_or("unknown clients".to_string(), |ip| ip.to_string())
+            );
+            too_many_requests(&request, retry_after)
+        }
+    }
+}
+
+/// Limits mutating requests per user, it must run after the user is known.
+pub(crate) async fn limit_by_user(
+    State(limiter): State<RateLimiter>,
+    auth_session: AuthSession,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Either an access token or a signed in session
+    let user_id = request
+        .extensions()
+        .get::<CurrentUser>()
+        .map(|user| user.id)
+        .or(auth_session.user.map(|user| user.id));
+
+    let Some(user_id) = user_id.filter(|_| !request.method().is_safe()) else {
+        return next.run(request).await;
+    };
+
+    match limiter.check(&user_id.to_string()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            warn!(
+                "Rate limited {} {} of user {}",
+                request.method(),
+                request.uri().path(),
+                user_id
+            );
+            too_many_requests(&request, retry_after)
+        }
+    }
+}
+
+/// Datastar requests get a toast, as they would otherwise fail silently.
+fn too_many_requests(request: &Request, retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs_f64().ceil() as u64;
+    let headers = [(RETRY_AFTER, seconds.max(1).to_string())];
+
+    if !request.headers().contains_key("datastar-request") {
+        return (StatusCode::TOO_MANY_REQUESTS, headers, "Too many requests").into_response();
+    }
+
+    let toast = ToastFragment::error("Too many requests, please wait a moment and try again.");
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        headers,
+        Sse(stream! {
+            yield toast.fragment().unwrap().into();
+        }),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_minute: u32, burst: u32) -> RateLimiter {
+        RateLimiter::new("test", RateLimit { per_minute, burst })
+    }
+
+    #[test]
+    fn allows_the_burst_then_limits() {
+        let limiter = limiter(60, 3);
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check("192.0.2.1"), Ok(()));
+        }
+
+        let retry_after = limiter.check("192.0.2.1").unwrap_err();
+        assert!(retry_after > Duration::ZERO);
+        assert!(retry_after <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn keys_have_their_own_buckets() {
+        let limiter = limiter(60, 1);
+
+        assert_eq!(limiter.check("192.0.2.1"), Ok(()));
+        assert!(limiter.check("192.0.2.1").is_err());
+        assert_eq!(limiter.check("192.0.2.2"), Ok(()));
+    }
+
+    #[test]
+    fn zero_per_minute_disables_the_limit() {
+        let limiter = limiter(0, 1);
+
+        for _ in 0..100 {
+            assert_eq!(limiter.check("192.0.2.1"), Ok(()));
+        }
+    }
+
+    #[test]
+    fn burst_is_at_least_one() {
+        let limiter = limiter(60, 0);
+
+        assert_eq!(limiter.check("192.0.2.1"), Ok(()));
+        assert!(limiter.check("192.0.2.1").is_err());
+    }
+
+    #[test]
+    fn groups_ipv6_addresses_by_network() {
+        let limiter = ClientRateLimiter::new(
+            "test",
+            RateLimit {
+                per_minute: 60,
+                burst: 1,
+            },
+            RateLimit {
+                per_minute: 60,
+                burst: 1,
+            },
+        );
+
+        assert_eq!(limiter.check("2001:db8::1".parse().ok()), Ok(()));
+        assert!(limiter.check("2001:db8::ffff:1".parse().ok()).is_err());
+        assert_eq!(limiter.check("2001:db8:0:1::1".parse().ok()), Ok(()));
+        assert_eq!(client_key("::ffff:192.0.2.1".parse().unwrap()), "192.0.2.1");
+    }
+
+    #[test]
+    fn unknown_clients_share_a_bucket_with_its_own_limit() {
+        let limiter = ClientRateLimiter::new(
+            "test",
+            RateLimit {
+                per_minute: 60,
+                burst: 1,
+            },
+            RateLimit {
+                per_minute: 60,
+                burst: 2,
+            },
+        );
+
+        assert_eq!(limiter.check(None), Ok(()));
+        assert_eq!(limiter.check(None), Ok(()));
+        assert!(limiter.check(None).is_err());
+        assert_eq!(limiter.check("192.0.2.1".parse().ok()), Ok(()));
+    }
+}
@@ -0,0 +1,83 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::Request,
+    http::{Method, header::CONTENT_TYPE},
+};
+use tower::ServiceExt;
+use tower_sessions::Session;
+use tracing::{error, info, warn};
+
+use crate::{
+    auth::CurrentUser,
+    fragments::ToastFragment,
+    model::{PendingAction, UserId},
+};
+
+/// Session key of the toast that is shown on the next page load.
+const TOAST_KEY: &str = "toast";
+
+/// Replays [`PendingAction`]s through the routes they were originally sent to.
+#[derive(Clone)]
+pub(crate) struct Replayer {
+    router: Router,
+}
+
+impl Replayer {
+    /// The routes must not require a login, replayed requests already carry a [`CurrentUser`].
+    pub fn new(router: Router) -> Self {
+        Self { router }
+    }
+
+    /// Replays the action on behalf of the user, the outcome is shown as toast on the next
+    /// page load.
+    pub async fn replay(&self, session: &Session, user_id: UserId, action: PendingAction) {
+        info!("Replaying {} {} after login", action.method, action.path);
+
+        let toast = match self.send(user_id, action).await {
+            Ok(()) => ToastFragment::success("Your last change was saved."),
+            Err(reason) => {
+                warn!("Replaying the pending action failed: {}", reason);
+                ToastFragment::error("Your last change couldn't be saved, please try again.")
+            }
+        };
+
+        if let Err(err) = session.insert(TOAST_KEY, toast).await {
+            error!("Failed to store toast in session: {:?}", err);
+        }
+    }
+
+    async fn send(&self, user_id: UserId, action: PendingAction) -> Result<(), String> {
+        let method = Method::from_bytes(action.method.as_bytes()).map_err(|e| e.to_string())?;
+
+        let request = Request::builder()
+            .method(method)
+            .uri(&action.path)
+            .header(CONTENT_TYPE, "application/json")
+            .header("datastar-request", "true")
+            .extension(CurrentUser { id: user_id })
+            .body(Body::from(action.signals.to_string()))
+            .map_err(|e| e.to_string())?;
+
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .unwrap_or_else(|infallible| match infallible {});
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("status {}", response.status()))
+        }
+    }
+}
+
+/// Removes the toast of a replayed action from the session, it is only shown once.
+pub(crate) async fn take_toast(session: &Session) -> Option<ToastFragment> {
+    session.remove(TOAST_KEY).await.unwrap_or_else(|err| {
+        warn!("Failed to read toast from session: {:?}", err);
+        None
+    })
+}
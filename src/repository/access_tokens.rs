@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Sqlite};
+use tracing::instrument;
+
+use super::RepositoryError;
+use crate::model::{AccessToken, AccessTokenId, UserId};
+
+const COLUMNS: &str = "id, user_id, name, scopes, created_at, expires_at, last_used_at";
+
+#[derive(Debug, Clone)]
+pub(crate) struct AccessTokenRepository {
+    db: Pool<Sqlite>,
+}
+
+impl AccessTokenRepository {
+    pub fn new(db: Pool<Sqlite>) -> Self {
+        Self { db }
+    }
+
+    #[instrument(skip(self, token, token_hash), fields(id = %token.id))]
+    pub async fn create(
+        &self,
+        token: &AccessToken,
+        token_hash: &[u8],
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO Access_Tokens (id, user_id, name, token_hash, scopes, created_at, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(token.id)
+        .bind(token.user_id)
+        .bind(&token.name)
+        .bind(token_hash)
+        .bind(&token.scopes)
+        .bind(token.created_at)
+        .bind(token.expires_at)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Finds the token with the given hash, tokens of disabled users are never found.
+    #[instrument(skip(self, token_hash))]
+    pub async fn find_by_hash(
+        &self,
+        token_hash: &[u8],
+    ) -> Result<Option<AccessToken>, RepositoryError> {
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM Access_Tokens WHERE token_hash = ? AND user_id NOT IN (SELECT id FROM Users WHERE disabled)"
+        ))
+        .bind(token_hash)
+        .fetch_optional(&self.db)
+        .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_all(&self, user_id: UserId) -> Result<Vec<AccessToken>, RepositoryError> {
+        Ok(sqlx::query_as(&format!(
+            "SELECT {COLUMNS} FROM Access_Tokens WHERE user_id = ? ORDER BY created_at"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn update_last_used(
+        &self,
+        id: AccessTokenId,
+        last_used_at: DateTime<Utc>,
+    ) -> Result<u64, RepositoryError> {
+        Ok(
+            sqlx::query("UPDATE Access_Tokens SET last_used_at = ? WHERE id = ?")
+                .bind(last_used_at)
+                .bind(id)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    #[instrument(skip(self))]
+    pub async fn delete(&self, user_id: UserId, id: AccessTokenId) -> Result<u64, RepositoryError> {
+        Ok(
+            sqlx::query("DELETE FROM Access_Tokens WHERE user_id = ? AND id = ?")
+                .bind(user_id)
+                .bind(id)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+}
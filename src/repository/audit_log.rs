@@ -0,0 +1,71 @@
+use chrono::Utc;
+use sqlx::{Pool, QueryBuilder, Sqlite};
+use tracing::instrument;
+
+use super::RepositoryError;
+use crate::model::{AuditEntry, AuditFilter, AuditRecord};
+
+/// The audit log is append-only, the table rejects updates and deletes.
+#[derive(Debug, Clone)]
+pub(crate) struct AuditLogRepository {
+    db: Pool<Sqlite>,
+}
+
+impl AuditLogRepository {
+    pub fn new(db: Pool<Sqlite>) -> Self {
+        Self { db }
+    }
+
+    #[instrument(skip(self, record), fields(event = record.event.as_str()))]
+    pub async fn append(&self, record: &AuditRecord) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO Audit_Log (occurred_at, event, user_id, actor_id, ip, user_agent, details) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(Utc::now())
+        .bind(record.event.as_str())
+        .bind(record.user_id)
+        .bind(record.actor_id)
+        .bind(&record.ip)
+        .bind(&record.user_agent)
+        .bind(&record.details)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Finds the entries matching the filter, most recent first.
+    #[instrument(skip(self))]
+    pub async fn find(
+        &self,
+        filter: &AuditFilter,
+        limit: Option<u32>,
+    ) -> Result<Vec<AuditEntry>, RepositoryError> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM Audit_Log WHERE 1 = 1");
+
+        if let Some(event) = filter.event {
+            query.push(" AND event = ").push_bind(event.as_str());
+        }
+        if let Some(user_id) = filter.user_id {
+            query
+                .push(" AND (user_id = ")
+                .push_bind(user_id)
+                .push(" OR actor_id = ")
+                .push_bind(user_id)
+                .push(")");
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND occurred_at >= ").push_bind(since);
+        }
+        if let Some(until) = filter.until {
+            query.push(" AND occurred_at < ").push_bind(until);
+        }
+
+        query.push(" ORDER BY id DESC");
+        if let Some(limit) = limit {
+            query.push(" LIMIT ").push_bind(limit);
+        }
+
+        Ok(query.build_query_as().fetch_all(&self.db).await?)
+    }
+}
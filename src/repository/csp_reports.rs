@@ -0,0 +1,77 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Sqlite};
+use tracing::instrument;
+
+use super::RepositoryError;
+use crate::model::{CspReport, CspViolation};
+
+#[derive(Debug, Clone)]
+pub(crate) struct CspReportRepository {
+    db: Pool<Sqlite>,
+}
+
+impl CspReportRepository {
+    pub fn new(db: Pool<Sqlite>) -> Self {
+        Self { db }
+    }
+
+    /// Stores the violation, or counts it if an identical one is already stored.
+    #[instrument(skip(self, violation, user_agent))]
+    pub async fn record(
+        &self,
+        violation: &CspViolation,
+        user_agent: Option<&str>,
+        seen_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO Csp_Reports (document_uri, directive, blocked_uri, source_file, line_number, disposition, sample, user_agent, first_seen_at, last_seen_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (document_uri, directive, blocked_uri, source_file, line_number, disposition)
+            DO UPDATE SET count = count + 1, sample = excluded.sample, user_agent = excluded.user_agent, last_seen_at = excluded.last_seen_at",
+        )
+        .bind(&violation.document_uri)
+        .bind(&violation.directive)
+        .bind(&violation.blocked_uri)
+        .bind(&violation.source_file)
+        .bind(violation.line_number)
+        .bind(&violation.disposition)
+        .bind(&violation.sample)
+        .bind(user_agent)
+        .bind(seen_at)
+        .bind(seen_at)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Finds the most recently reported violations.
+    #[instrument(skip(self))]
+    pub async fn find_recent(&self, limit: u32) -> Result<Vec<CspReport>, RepositoryError> {
+        Ok(
+            sqlx::query_as("SELECT * FROM Csp_Reports ORDER BY last_seen_at DESC LIMIT ?")
+                .bind(limit)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    /// Deletes all but the `keep` most recently reported violations.
+    #[instrument(skip(self))]
+    pub async fn delete_oldest(&self, keep: u32) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query(
+            "DELETE FROM Csp_Reports WHERE id NOT IN (SELECT id FROM Csp_Reports ORDER BY last_seen_at DESC LIMIT ?)",
+        )
+        .bind(keep)
+        .execute(&self.db)
+        .await?
+        .rows_affected())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn delete_all(&self) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query("DELETE FROM Csp_Reports")
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use sqlx::{Pool, Sqlite, migrate::MigrateError};
+use tracing::instrument;
+
+use super::RepositoryError;
+use crate::{
+    db::{self, MigrationState},
+    model::{AccessToken, Note, StoredNote, User, UserId},
+};
+
+/// Database wide queries of the administrative commands and the readiness check.
+#[derive(Debug, Clone)]
+pub(crate) struct MaintenanceRepository {
+    db: Pool<Sqlite>,
+}
+
+impl MaintenanceRepository {
+    pub fn new(db: Pool<Sqlite>) -> Self {
+        Self { db }
+    }
+
+    /// Round-trip to the database.
+    #[instrument(skip(self))]
+    pub async fn ping(&self) -> Result<(), RepositoryError> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+
+        Ok(())
+    }
+
+    /// Migrations that aren't applied as they are in the `migrations` directory.
+    #[instrument(skip(self))]
+    pub async fn outdated_migrations(
+        &self,
+    ) -> Result<Vec<(i64, String, MigrationState)>, MigrateError> {
+        Ok(db::migration_status(&self.db)
+            .await?
+            .into_iter()
+            .filter(|(_, _, state)| *state != MigrationState::Applied)
+            .collect())
+    }
+
+    /// Writes a consistent copy of the database to `path`, while it is in use.
+    #[instrument(skip(self))]
+    pub async fn backup(&self, path: &Path) -> Result<(), RepositoryError> {
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy())
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Problems SQLite finds in the database file, none if it is intact.
+    #[instrument(skip(self))]
+    pub async fn integrity_check(&self) -> Result<Vec<String>, RepositoryError> {
+        let results: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(results
+            .into_iter()
+            .filter(|result| result != "ok")
+            .collect())
+    }
+
+    /// Tables with rows that reference a missing row, and the table of the missing row.
+    #[instrument(skip(self))]
+    pub async fn foreign_key_violations(&self) -> Result<Vec<(String, String)>, RepositoryError> {
+        let rows: Vec<(String, Option<i64>, String, i64)> =
+            sqlx::query_as("PRAGMA foreign_key_check")
+                .fetch_all(&self.db)
+                .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(table, _, parent, _)| (table, parent))
+            .collect())
+    }
+
+    /// Notes don't reference their owner, so they aren't covered by the foreign key check.
+    #[instrument(skip(self))]
+    pub async fn count_orphaned_notes(&self) -> Result<i64, RepositoryError> {
+        Ok(sqlx::query_scalar(
+            "SELECT COUNT(*) FROM Notes WHERE owner NOT IN (SELECT id FROM Users)",
+        )
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn count_orphaned_sessions(&self) -> Result<i64, RepositoryError> {
+        Ok(sqlx::query_scalar(
+            "SELECT COUNT(*) FROM Sessions WHERE user_id IS NOT NULL AND user_id NOT IN (SELECT id FROM Users)",
+        )
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_all_users(&self) -> Result<Vec<User>, RepositoryError> {
+        Ok(sqlx::query_as("SELECT * FROM Users")
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    /// Notes of all users, without the ones in the trash.
+    #[instrument(skip(self))]
+    pub async fn find_all_notes(&self) -> Result<Vec<Note>, RepositoryError> {
+        Ok(
+            sqlx::query_as("SELECT * FROM Notes WHERE deleted_at IS NULL")
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    /// Notes of all users including the trash, for `migrate-data`.
+    #[instrument(skip(self))]
+    pub async fn find_all_stored_notes(&self) -> Result<Vec<StoredNote>, RepositoryError> {
+        Ok(sqlx::query_as("SELECT * FROM Notes")
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_all_access_tokens(&self) -> Result<Vec<AccessToken>, RepositoryError> {
+        Ok(sqlx::query_as(
+            "SELECT id, user_id, name, scopes, created_at, expires_at, last_used_at FROM Access_Tokens",
+        )
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Space separated roles of the authenticated users.
+    #[instrument(skip(self))]
+    pub async fn find_session_user_roles(&self) -> Result<Vec<(UserId, String)>, RepositoryError> {
+        Ok(sqlx::query_as("SELECT user_id, roles FROM Session_Users")
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_audit_events(&self) -> Result<Vec<String>, RepositoryError> {
+        Ok(sqlx::query_scalar("SELECT DISTINCT event FROM Audit_Log")
+            .fetch_all(&self.db)
+            .await?)
+    }
+}
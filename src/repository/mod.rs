@@ -1,9 +1,26 @@
+mod access_tokens;
+mod audit_log;
+mod csp_reports;
+mod maintenance;
 mod notes;
+mod postgres_notes;
+mod sessions;
+mod users;
 
-pub(crate) use notes::NoteRepository;
+pub(crate) use access_tokens::AccessTokenRepository;
+pub(crate) use audit_log::AuditLogRepository;
+pub(crate) use csp_reports::CspReportRepository;
+pub(crate) use maintenance::MaintenanceRepository;
+pub(crate) use notes::{NoteStorage, SqliteNoteRepository};
+pub(crate) use postgres_notes::PostgresNoteRepository;
+pub(crate) use sessions::SessionRepository;
+pub(crate) use users::UserRepository;
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum RepositoryError {
     #[error(transparent)]
     DatabaseError(#[from] sqlx::Error),
+
+    #[error(transparent)]
+    SerializationError(#[from] serde_json::Error),
 }
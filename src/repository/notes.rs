@@ -1,21 +1,61 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use sqlx::{Pool, Sqlite};
 use tracing::instrument;
 
 use super::RepositoryError;
 use crate::model::{Note, NoteId, UserId};
 
+/// Storage of the notes, either the SQLite database or PostgreSQL, see `db::note_storage`.
+#[async_trait]
+pub(crate) trait NoteStorage: Debug + Send + Sync {
+    async fn create(&self, owner: UserId, content: &str) -> Result<NoteId, RepositoryError>;
+
+    /// Moves the note to the trash, see `purge_deleted`.
+    async fn delete(&self, owner: UserId, id: NoteId) -> Result<u64, RepositoryError>;
+
+    /// Removes the notes that were moved to the trash before `before` for good.
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<u64, RepositoryError>;
+
+    async fn find_by_id(&self, owner: UserId, id: NoteId) -> Result<Note, RepositoryError>;
+
+    async fn find_all(&self, owner: UserId) -> Result<Vec<Note>, RepositoryError>;
+
+    /// Number of notes per user, without the ones in the trash.
+    async fn count_by_owner(&self) -> Result<HashMap<UserId, i64>, RepositoryError>;
+
+    async fn update_checked(
+        &self,
+        owner: UserId,
+        id: NoteId,
+        checked: bool,
+    ) -> Result<u64, RepositoryError>;
+
+    async fn update_content(
+        &self,
+        owner: UserId,
+        id: NoteId,
+        content: &str,
+    ) -> Result<u64, RepositoryError>;
+}
+
 #[derive(Debug, Clone)]
-pub(crate) struct NoteRepository {
+pub(crate) struct SqliteNoteRepository {
     db: Pool<Sqlite>,
 }
 
-impl NoteRepository {
+impl SqliteNoteRepository {
     pub fn new(db: Pool<Sqlite>) -> Self {
         Self { db }
     }
+}
 
+#[async_trait]
+impl NoteStorage for SqliteNoteRepository {
     #[instrument(skip(self, content))]
-    pub async fn create(&self, owner: UserId, content: &str) -> Result<NoteId, RepositoryError> {
+    async fn create(&self, owner: UserId, content: &str) -> Result<NoteId, RepositoryError> {
         let uuid = NoteId::new_v4();
 
         sqlx::query("INSERT INTO Notes (id, owner, content, checked) VALUES (?, ?, ?, ?)")
@@ -30,19 +70,31 @@ impl NoteRepository {
     }
 
     #[instrument(skip(self))]
-    pub async fn delete(&self, owner: UserId, id: NoteId) -> Result<u64, RepositoryError> {
-        Ok(sqlx::query("DELETE FROM Notes WHERE owner = ? AND id = ?")
-            .bind(owner)
-            .bind(id)
+    async fn delete(&self, owner: UserId, id: NoteId) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query(
+            "UPDATE Notes SET deleted_at = ? WHERE owner = ? AND id = ? AND deleted_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(owner)
+        .bind(id)
+        .execute(&self.db)
+        .await?
+        .rows_affected())
+    }
+
+    #[instrument(skip(self))]
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query("DELETE FROM Notes WHERE deleted_at < ?")
+            .bind(before)
             .execute(&self.db)
             .await?
             .rows_affected())
     }
 
     #[instrument(skip(self))]
-    pub async fn find_by_id(&self, owner: UserId, id: NoteId) -> Result<Note, RepositoryError> {
+    async fn find_by_id(&self, owner: UserId, id: NoteId) -> Result<Note, RepositoryError> {
         Ok(
-            sqlx::query_as("SELECT * FROM Notes WHERE owner = ? AND id = ?")
+            sqlx::query_as("SELECT * FROM Notes WHERE owner = ? AND id = ? AND deleted_at IS NULL")
                 .bind(owner)
                 .bind(id)
                 .fetch_one(&self.db)
@@ -51,46 +103,59 @@ impl NoteRepository {
     }
 
     #[instrument(skip(self))]
-    pub async fn find_all(&self, owner: UserId) -> Result<Vec<Note>, RepositoryError> {
-        Ok(sqlx::query_as("SELECT * FROM Notes WHERE owner = ?")
-            .bind(owner)
-            .fetch_all(&self.db)
-            .await?)
+    async fn find_all(&self, owner: UserId) -> Result<Vec<Note>, RepositoryError> {
+        Ok(
+            sqlx::query_as("SELECT * FROM Notes WHERE owner = ? AND deleted_at IS NULL")
+                .bind(owner)
+                .fetch_all(&self.db)
+                .await?,
+        )
     }
 
     #[instrument(skip(self))]
-    pub async fn update_checked(
+    async fn count_by_owner(&self) -> Result<HashMap<UserId, i64>, RepositoryError> {
+        Ok(sqlx::query_as(
+            "SELECT owner, COUNT(*) FROM Notes WHERE deleted_at IS NULL GROUP BY owner",
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn update_checked(
         &self,
         owner: UserId,
         id: NoteId,
         checked: bool,
     ) -> Result<u64, RepositoryError> {
-        Ok(
-            sqlx::query("UPDATE Notes SET checked = ? WHERE owner = ? AND id = ?")
-                .bind(checked)
-                .bind(owner)
-                .bind(id)
-                .execute(&self.db)
-                .await?
-                .rows_affected(),
+        Ok(sqlx::query(
+            "UPDATE Notes SET checked = ? WHERE owner = ? AND id = ? AND deleted_at IS NULL",
         )
+        .bind(checked)
+        .bind(owner)
+        .bind(id)
+        .execute(&self.db)
+        .await?
+        .rows_affected())
     }
 
     #[instrument(skip(self, content))]
-    pub async fn update_content(
+    async fn update_content(
         &self,
         owner: UserId,
         id: NoteId,
         content: &str,
     ) -> Result<u64, RepositoryError> {
-        Ok(
-            sqlx::query("UPDATE Notes SET content = ? WHERE owner = ? AND id = ?")
-                .bind(content)
-                .bind(owner)
-                .bind(id)
-                .execute(&self.db)
-                .await?
-                .rows_affected(),
+        Ok(sqlx::query(
+            "UPDATE Notes SET content = ? WHERE owner = ? AND id = ? AND deleted_at IS NULL",
         )
+        .bind(content)
+        .bind(owner)
+        .bind(id)
+        .execute(&self.db)
+        .await?
+        .rows_affected())
     }
 }
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use tracing::instrument;
+
+use super::{NoteStorage, RepositoryError};
+use crate::model::{Note, NoteId, StoredNote, UserId};
+
+/// Notes in PostgreSQL, the users and sessions stay in the SQLite database.
+#[derive(Debug, Clone)]
+pub(crate) struct PostgresNoteRepository {
+    db: Pool<Postgres>,
+}
+
+impl PostgresNoteRepository {
+    pub fn new(db: Pool<Postgres>) -> Self {
+        Self { db }
+    }
+
+    /// Inserts the notes with their ids in one transaction, notes that already exist are
+    /// skipped so an interrupted copy can be repeated. Returns the number of inserted notes.
+    #[instrument(skip_all, fields(notes = notes.len()))]
+    pub async fn import(&self, notes: &[StoredNote]) -> Result<u64, RepositoryError> {
+        let mut transaction = self.db.begin().await?;
+        let mut inserted = 0;
+
+        for StoredNote { note, deleted_at } in notes {
+            inserted += sqlx::query(
+                "INSERT INTO Notes (id, owner, content, checked, deleted_at) \
+                 VALUES ($1, $2, $3, $4, $5) ON CONFLICT (id) DO NOTHING",
+            )
+            .bind(note.id)
+            .bind(note.owner)
+            .bind(&note.content)
+            .bind(note.checked)
+            .bind(deleted_at)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
+        }
+
+        transaction.commit().await?;
+        Ok(inserted)
+    }
+
+    /// Removes all notes of the user for good, to undo an `import`.
+    #[instrument(skip(self))]
+    pub async fn delete_all(&self, owner: UserId) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query("DELETE FROM Notes WHERE owner = $1")
+            .bind(owner)
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+
+    /// Notes of all users, for `check`.
+    #[instrument(skip(self))]
+    pub async fn find_all_notes(&self) -> Result<Vec<Note>, RepositoryError> {
+        Ok(
+            sqlx::query_as("SELECT id, owner, content, checked FROM Notes")
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+}
+
+#[async_trait]
+impl NoteStorage for PostgresNoteRepository {
+    #[instrument(skip(self, content))]
+    async fn create(&self, owner: UserId, content: &str) -> Result<NoteId, RepositoryError> {
+        let uuid = NoteId::new_v4();
+
+        sqlx::query("INSERT INTO Notes (id, owner, content, checked) VALUES ($1, $2, $3, $4)")
+            .bind(uuid)
+            .bind(owner)
+            .bind(content)
+            .bind(false)
+            .execute(&self.db)
+            .await?;
+
+        Ok(uuid)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, owner: UserId, id: NoteId) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query(
+            "UPDATE Notes SET deleted_at = $1 WHERE owner = $2 AND id = $3 AND deleted_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(owner)
+        .bind(id)
+        .execute(&self.db)
+        .await?
+        .rows_affected())
+    }
+
+    #[instrument(skip(self))]
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query("DELETE FROM Notes WHERE deleted_at < $1")
+            .bind(before)
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+
+    #[instrument(skip(self))]
+    async fn find_by_id(&self, owner: UserId, id: NoteId) -> Result<Note, RepositoryError> {
+        Ok(sqlx::query_as(
+            "SELECT id, owner, content, checked FROM Notes WHERE owner = $1 AND id = $2 AND deleted_at IS NULL",
+        )
+        .bind(owner)
+        .bind(id)
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    #[instrument(skip(self))]
+    async fn find_all(&self, owner: UserId) -> Result<Vec<Note>, RepositoryError> {
+        Ok(sqlx::query_as(
+            "SELECT id, owner, content, checked FROM Notes WHERE owner = $1 AND deleted_at IS NULL",
+        )
+        .bind(owner)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    #[instrument(skip(self))]
+    async fn count_by_owner(&self) -> Result<HashMap<UserId, i64>, RepositoryError> {
+        Ok(sqlx::query_as(
+            "SELECT owner, COUNT(*) FROM Notes WHERE deleted_at IS NULL GROUP BY owner",
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn update_checked(
+        &self,
+        owner: UserId,
+        id: NoteId,
+        checked: bool,
+    ) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query(
+            "UPDATE Notes SET checked = $1 WHERE owner = $2 AND id = $3 AND deleted_at IS NULL",
+        )
+        .bind(checked)
+        .bind(owner)
+        .bind(id)
+        .execute(&self.db)
+        .await?
+        .rows_affected())
+    }
+
+    #[instrument(skip(self, content))]
+    async fn update_content(
+        &self,
+        owner: UserId,
+        id: NoteId,
+        content: &str,
+    ) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query(
+            "UPDATE Notes SET content = $1 WHERE owner = $2 AND id = $3 AND deleted_at IS NULL",
+        )
+        .bind(content)
+        .bind(owner)
+        .bind(id)
+        .execute(&self.db)
+        .await?
+        .rows_affected())
+    }
+}
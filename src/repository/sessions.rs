@@ -0,0 +1,537 @@
+use std::{cmp::Reverse, collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, Pool, Sqlite};
+use tower_sessions::{
+    SessionStore,
+    cookie::time::OffsetDateTime,
+    session::{Id, Record},
+    session_store::{self, ExpiredDeletion},
+};
+use tracing::{debug, instrument, warn};
+
+use super::RepositoryError;
+use crate::{
+    model::{ActiveSession, LoginId, Role, SessionDevice, SessionUser, SessionUserId, UserId},
+    session_cookie::CredentialsCipher,
+};
+
+/// Session store of the session layer, also keeps the authenticated users of the sessions.
+#[derive(Debug, Clone)]
+pub(crate) struct SessionRepository {
+    db: Pool<Sqlite>,
+    cipher: CredentialsCipher,
+}
+
+#[derive(FromRow)]
+struct SessionUserRow {
+    login_id: LoginId,
+    user_id: UserId,
+    /// JSON encoded [`crate::model::SessionCredentials`], encrypted with the session key.
+    credentials: Vec<u8>,
+    auth_hash: Vec<u8>,
+    /// JSON encoded [`crate::model::PendingAction`].
+    pending_action: Option<String>,
+    /// Space separated list of [`Role`]s.
+    roles: String,
+    expires_at: DateTime<Utc>,
+    last_health_check: DateTime<Utc>,
+}
+
+impl SessionRepository {
+    pub fn new(db: Pool<Sqlite>, cipher: CredentialsCipher) -> Self {
+        Self { db, cipher }
+    }
+
+    /// Finds the authenticated user of a login, unless it already expired.
+    #[instrument(skip(self))]
+    pub async fn find_user(
+        &self,
+        id: SessionUserId,
+    ) -> Result<Option<SessionUser>, RepositoryError> {
+        let now = Utc::now();
+        let row: Option<SessionUserRow> = sqlx::query_as(
+            "SELECT * FROM Session_Users WHERE login_id = ? AND user_id = ? AND expires_at > ?",
+        )
+        .bind(id.login_id)
+        .bind(id.user_id)
+        .bind(now)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let Some(credentials) = self.cipher.open(&row.credentials) else {
+            warn!(
+                "Credentials of user {} can't be decrypted, the session key changed",
+                id.user_id
+            );
+            return Ok(None);
+        };
+
+        Ok(Some(SessionUser {
+            id: row.user_id,
+            login_id: row.login_id,
+            credentials: serde_json::from_slice(&credentials)?,
+            auth_hash: row.auth_hash,
+            pending_action: row
+                .pending_action
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+            roles: row
+                .roles
+                .split_whitespace()
+                .filter_map(|role| role.parse().ok())
+                .collect(),
+            expiration: (row.expires_at - now).to_std().unwrap_or_default(),
+            last_health_check: row.last_health_check,
+        }))
+    }
+
+    /// Inserts or replaces the authenticated user of a login, it expires after
+    /// [`SessionUser::expiration`].
+    #[instrument(skip(self, user), fields(id = %user.id, login_id = %user.login_id))]
+    pub async fn save_user(&self, user: &SessionUser) -> Result<(), RepositoryError> {
+        let expires_at = Utc::now() + user.expiration;
+
+        sqlx::query(
+            "INSERT OR REPLACE INTO Session_Users (login_id, user_id, credentials, auth_hash, pending_action, roles, expires_at, last_health_check) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(user.login_id)
+        .bind(user.id)
+        .bind(self.cipher.seal(&serde_json::to_vec(&user.credentials)?))
+        .bind(&user.auth_hash)
+        .bind(
+            user.pending_action
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?,
+        )
+        .bind(
+            user.roles
+                .iter()
+                .map(Role::as_str)
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+        .bind(expires_at)
+        .bind(user.last_health_check)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn delete_login(&self, id: SessionUserId) -> Result<u64, RepositoryError> {
+        Ok(
+            sqlx::query("DELETE FROM Session_Users WHERE login_id = ? AND user_id = ?")
+                .bind(id.login_id)
+                .bind(id.user_id)
+                .execute(&self.db)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    /// Deletes the authenticated user of every login of the user.
+    #[instrument(skip(self))]
+    pub async fn delete_user(&self, user_id: UserId) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query("DELETE FROM Session_Users WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+
+    /// Finds the signed in sessions of the user that haven't expired yet.
+    #[instrument(skip(self))]
+    pub async fn find_by_user(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<ActiveSession>, RepositoryError> {
+        let rows: Vec<(String, String, i64)> = sqlx::query_as(
+            "SELECT id, data, expiry_date FROM Sessions WHERE user_id = ? AND expiry_date > ?",
+        )
+        .bind(user_id)
+        .bind(Utc::now().timestamp())
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut sessions = Vec::with_capacity(rows.len());
+        for (id, data, expiry_date) in rows {
+            let Some(device) = session_device(&serde_json::from_str(&data)?) else {
+                continue;
+            };
+
+            sessions.push(ActiveSession {
+                id,
+                device,
+                expires_at: DateTime::from_timestamp(expiry_date, 0).unwrap_or_default(),
+            });
+        }
+
+        // Most recently used first
+        sessions.sort_by_key(|session| Reverse(session.device.last_seen_at));
+
+        Ok(sessions)
+    }
+
+    /// Deletes a session of the user and its login, which signs it out.
+    #[instrument(skip(self, id))]
+    pub async fn delete_by_user(&self, user_id: UserId, id: &str) -> Result<u64, RepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            "DELETE FROM Session_Users WHERE login_id IN (SELECT login_id FROM Sessions WHERE user_id = ? AND id = ?)",
+        )
+        .bind(user_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        let deleted = sqlx::query("DELETE FROM Sessions WHERE user_id = ? AND id = ?")
+            .bind(user_id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    /// Deletes all sessions of the user, which signs it out everywhere.
+    #[instrument(skip(self))]
+    pub async fn delete_all_by_user(&self, user_id: UserId) -> Result<u64, RepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query("DELETE FROM Session_Users WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        let deleted = sqlx::query("DELETE FROM Sessions WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    /// Deletes all sessions of the user and their logins, except the session with id `keep`.
+    #[instrument(skip(self, keep))]
+    pub async fn delete_others_by_user(
+        &self,
+        user_id: UserId,
+        keep: &str,
+    ) -> Result<u64, RepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            "DELETE FROM Session_Users WHERE login_id IN (SELECT login_id FROM Sessions WHERE user_id = ? AND id != ?)",
+        )
+        .bind(user_id)
+        .bind(keep)
+        .execute(&mut *tx)
+        .await?;
+        let deleted = sqlx::query("DELETE FROM Sessions WHERE user_id = ? AND id != ?")
+            .bind(user_id)
+            .bind(keep)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    /// Deletes all sessions and logins of the user, except those of the login `keep`.
+    #[instrument(skip(self))]
+    pub async fn delete_other_logins(
+        &self,
+        user_id: UserId,
+        keep: LoginId,
+    ) -> Result<u64, RepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query("DELETE FROM Session_Users WHERE user_id = ? AND login_id != ?")
+            .bind(user_id)
+            .bind(keep)
+            .execute(&mut *tx)
+            .await?;
+        let deleted = sqlx::query(
+            "DELETE FROM Sessions WHERE user_id = ? AND (login_id IS NULL OR login_id != ?)",
+        )
+        .bind(user_id)
+        .bind(keep)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    /// Deletes expired sessions and users every `period`, runs until the server stops.
+    pub async fn continuously_delete_expired(self, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(error) = self.delete_expired().await {
+                warn!("Failed to delete expired sessions: {}", error);
+            }
+        }
+    }
+}
+
+fn session_device(data: &HashMap<String, serde_json::Value>) -> Option<SessionDevice> {
+    data.get(SessionDevice::SESSION_KEY)
+        .and_then(|device| serde_json::from_value(device.clone()).ok())
+}
+
+fn backend_error(error: impl ToString) -> session_store::Error {
+    session_store::Error::Backend(error.to_string())
+}
+
+/// Columns of a session record, the device tells which user and login it belongs to.
+fn record_columns(
+    record: &Record,
+) -> session_store::Result<(String, Option<UserId>, Option<LoginId>)> {
+    let data = serde_json::to_string(&record.data)
+        .map_err(|error| session_store::Error::Encode(error.to_string()))?;
+    let device = session_device(&record.data);
+
+    Ok((
+        data,
+        device.as_ref().map(|device| device.user_id),
+        device.as_ref().map(|device| device.login_id),
+    ))
+}
+
+#[async_trait]
+impl SessionStore for SessionRepository {
+    #[instrument(skip(self, record))]
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let mut tx = self.db.begin().await.map_err(backend_error)?;
+
+        while sqlx::query("SELECT id FROM Sessions WHERE id = ?")
+            .bind(record.id.to_string())
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(backend_error)?
+            .is_some()
+        {
+            // Session id collision, pick another one
+            record.id = Id::default();
+        }
+
+        let (data, user_id, login_id) = record_columns(record)?;
+        sqlx::query(
+            "INSERT INTO Sessions (id, data, expiry_date, user_id, login_id) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(record.id.to_string())
+        .bind(data)
+        .bind(record.expiry_date.unix_timestamp())
+        .bind(user_id)
+        .bind(login_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(backend_error)?;
+
+        tx.commit().await.map_err(backend_error)
+    }
+
+    /// Only updates the session, a session that was revoked meanwhile stays deleted.
+    #[instrument(skip(self, record))]
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let (data, user_id, login_id) = record_columns(record)?;
+        let updated = sqlx::query(
+            "UPDATE Sessions SET data = ?, expiry_date = ?, user_id = ?, login_id = ? WHERE id = ?",
+        )
+        .bind(data)
+        .bind(record.expiry_date.unix_timestamp())
+        .bind(user_id)
+        .bind(login_id)
+        .bind(record.id.to_string())
+        .execute(&self.db)
+        .await
+        .map_err(backend_error)?
+        .rows_affected();
+
+        if updated == 0 {
+            debug!("Session was deleted while it was in use, not saving it");
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let row: Option<(String, i64)> = sqlx::query_as(
+            "SELECT data, expiry_date FROM Sessions WHERE id = ? AND expiry_date > ?",
+        )
+        .bind(session_id.to_string())
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .fetch_optional(&self.db)
+        .await
+        .map_err(backend_error)?;
+
+        let Some((data, expiry_date)) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(Record {
+            id: *session_id,
+            data: serde_json::from_str(&data)
+                .map_err(|error| session_store::Error::Decode(error.to_string()))?,
+            expiry_date: OffsetDateTime::from_unix_timestamp(expiry_date)
+                .map_err(|error| session_store::Error::Decode(error.to_string()))?,
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        sqlx::query("DELETE FROM Sessions WHERE id = ?")
+            .bind(session_id.to_string())
+            .execute(&self.db)
+            .await
+            .map_err(backend_error)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for SessionRepository {
+    #[instrument(skip(self))]
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        sqlx::query("DELETE FROM Sessions WHERE expiry_date <= ?")
+            .bind(OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&self.db)
+            .await
+            .map_err(backend_error)?;
+
+        sqlx::query("DELETE FROM Session_Users WHERE expires_at <= ?")
+            .bind(Utc::now())
+            .execute(&self.db)
+            .await
+            .map_err(backend_error)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use sqlx::sqlite::SqlitePoolOptions;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        config::SessionConfig, model::SessionCredentials, session_cookie::SessionCookieConfig,
+    };
+
+    async fn repository() -> SessionRepository {
+        // Every connection to an in-memory database has its own database
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::db::migrator().run(&db).await.unwrap();
+
+        let cookies = SessionCookieConfig::new(&SessionConfig::default(), false);
+        SessionRepository::new(db, cookies.credentials_cipher())
+    }
+
+    async fn sign_in(repository: &SessionRepository, user_id: UserId) -> SessionUserId {
+        let user = SessionUser {
+            id: user_id,
+            login_id: Uuid::new_v4(),
+            credentials: SessionCredentials::Password,
+            auth_hash: vec![1, 2, 3],
+            pending_action: None,
+            roles: HashSet::new(),
+            expiration: Duration::from_secs(3600),
+            last_health_check: Utc::now(),
+        };
+        repository.save_user(&user).await.unwrap();
+
+        sqlx::query(
+            "INSERT INTO Sessions (id, data, expiry_date, user_id, login_id) VALUES (?, '{}', ?, ?, ?)",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind((Utc::now() + Duration::from_secs(3600)).timestamp())
+        .bind(user.id)
+        .bind(user.login_id)
+        .execute(&repository.db)
+        .await
+        .unwrap();
+
+        user.key()
+    }
+
+    async fn count(repository: &SessionRepository, query: &str, user_id: UserId) -> i64 {
+        sqlx::query_scalar(query)
+            .bind(user_id)
+            .fetch_one(&repository.db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn deletes_the_other_logins_of_the_user() {
+        let repository = repository().await;
+        let user_id = UserId(Uuid::new_v4());
+        let other_user_id = UserId(Uuid::new_v4());
+        for id in [user_id, other_user_id] {
+            sqlx::query("INSERT INTO Users (id, username) VALUES (?, ?)")
+                .bind(id)
+                .bind(id.to_string())
+                .execute(&repository.db)
+                .await
+                .unwrap();
+        }
+
+        let current = sign_in(&repository, user_id).await;
+        let other = sign_in(&repository, user_id).await;
+        let other_user = sign_in(&repository, other_user_id).await;
+
+        let deleted = repository
+            .delete_other_logins(user_id, current.login_id)
+            .await
+            .unwrap();
+
+        assert_eq!(deleted, 1);
+        assert!(repository.find_user(current).await.unwrap().is_some());
+        assert!(repository.find_user(other).await.unwrap().is_none());
+        assert!(repository.find_user(other_user).await.unwrap().is_some());
+        assert_eq!(
+            count(
+                &repository,
+                "SELECT COUNT(*) FROM Sessions WHERE user_id = ?",
+                user_id
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &repository,
+                "SELECT COUNT(*) FROM Session_Users WHERE user_id = ?",
+                user_id
+            )
+            .await,
+            1
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use chrono::Utc;
+use sqlx::{Pool, Sqlite};
+use tracing::instrument;
+
+use super::RepositoryError;
+use crate::model::{Note, User, UserId, UserIdentity, UserOverview};
+
+/// Selects [`UserOverview`]s, binds the current unix timestamp to tell active sessions apart.
+const OVERVIEW_QUERY: &str = "SELECT id, username, password IS NOT NULL AS local, disabled,
+    (SELECT COUNT(*) FROM Access_Tokens WHERE Access_Tokens.user_id = Users.id) AS access_token_count,
+    (SELECT COUNT(*) FROM Sessions WHERE Sessions.user_id = Users.id AND Sessions.expiry_date > ?) AS session_count
+    FROM Users";
+
+#[derive(Debug, Clone)]
+pub(crate) struct UserRepository {
+    db: Pool<Sqlite>,
+}
+
+impl UserRepository {
+    pub fn new(db: Pool<Sqlite>) -> Self {
+        Self { db }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn exists(&self, id: UserId) -> Result<bool, RepositoryError> {
+        Ok(sqlx::query("SELECT id FROM Users WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?
+            .is_some())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_id(&self, id: UserId) -> Result<Option<User>, RepositoryError> {
+        Ok(sqlx::query_as("SELECT * FROM Users WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn is_disabled(&self, id: UserId) -> Result<bool, RepositoryError> {
+        Ok(
+            sqlx::query_scalar("SELECT disabled FROM Users WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?
+                .unwrap_or(false),
+        )
+    }
+
+    /// Lists all users with the number of their notes, access tokens and active sessions.
+    #[instrument(skip(self))]
+    pub async fn find_all_overviews(&self) -> Result<Vec<UserOverview>, RepositoryError> {
+        Ok(
+            sqlx::query_as(&format!("{OVERVIEW_QUERY} ORDER BY username"))
+                .bind(Utc::now().timestamp())
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_overview(&self, id: UserId) -> Result<Option<UserOverview>, RepositoryError> {
+        Ok(sqlx::query_as(&format!("{OVERVIEW_QUERY} WHERE id = ?"))
+            .bind(Utc::now().timestamp())
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn update_disabled(
+        &self,
+        id: UserId,
+        disabled: bool,
+    ) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query("UPDATE Users SET disabled = ? WHERE id = ?")
+            .bind(disabled)
+            .bind(id)
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+
+    /// Finds the local account with the given username.
+    #[instrument(skip(self))]
+    pub async fn find_local_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        Ok(
+            sqlx::query_as("SELECT * FROM Users WHERE username = ? AND password IS NOT NULL")
+                .bind(username)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<UserId>, RepositoryError> {
+        Ok(sqlx::query_scalar(
+            "SELECT user_id FROM User_Identities WHERE provider = ? AND subject = ?",
+        )
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&self.db)
+        .await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_identities(&self, id: UserId) -> Result<Vec<UserIdentity>, RepositoryError> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM User_Identities WHERE user_id = ? ORDER BY provider, subject",
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    /// Creates a new user together with the identity it signed in with.
+    #[instrument(skip(self))]
+    pub async fn create_with_identity(
+        &self,
+        id: UserId,
+        username: &str,
+        provider: &str,
+        subject: &str,
+    ) -> Result<(), RepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query("INSERT INTO Users (id, username) VALUES (?, ?)")
+            .bind(id)
+            .bind(username)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("INSERT INTO User_Identities (provider, subject, user_id) VALUES (?, ?, ?)")
+            .bind(provider)
+            .bind(subject)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        Ok(tx.commit().await?)
+    }
+
+    /// Creates a user exported from another instance, with its password hash, identities and
+    /// notes in one transaction. The notes must be empty if they are stored in PostgreSQL.
+    #[instrument(skip_all, fields(id = %user.id, notes = notes.len()))]
+    pub async fn create_imported(
+        &self,
+        user: &User,
+        identities: &[UserIdentity],
+        notes: &[Note],
+    ) -> Result<(), RepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query("INSERT INTO Users (id, username, password, disabled) VALUES (?, ?, ?, ?)")
+            .bind(user.id)
+            .bind(&user.username)
+            .bind(&user.password)
+            .bind(user.disabled)
+            .execute(&mut *tx)
+            .await?;
+
+        for identity in identities {
+            sqlx::query(
+                "INSERT INTO User_Identities (provider, subject, user_id) VALUES (?, ?, ?)",
+            )
+            .bind(&identity.provider)
+            .bind(&identity.subject)
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for note in notes {
+            sqlx::query("INSERT INTO Notes (id, owner, content, checked) VALUES (?, ?, ?, ?)")
+                .bind(note.id)
+                .bind(user.id)
+                .bind(&note.content)
+                .bind(note.checked)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        Ok(tx.commit().await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn add_identity(
+        &self,
+        id: UserId,
+        provider: &str,
+        subject: &str,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query("INSERT INTO User_Identities (provider, subject, user_id) VALUES (?, ?, ?)")
+            .bind(provider)
+            .bind(subject)
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self, password))]
+    pub async fn create_local(
+        &self,
+        id: UserId,
+        username: &str,
+        password: &str,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query("INSERT INTO Users (id, username, password) VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(username)
+            .bind(password)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self, password))]
+    pub async fn update_password(
+        &self,
+        id: UserId,
+        password: &str,
+    ) -> Result<u64, RepositoryError> {
+        Ok(sqlx::query("UPDATE Users SET password = ? WHERE id = ?")
+            .bind(password)
+            .bind(id)
+            .execute(&self.db)
+            .await?
+            .rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::config::DEFAULT_PROVIDER;
+
+    #[tokio::test]
+    async fn migration_creates_the_users_of_existing_notes() {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        // A database from before there were users
+        sqlx::raw_sql(include_str!("../../migrations/000_create_database.sql"))
+            .execute(&db)
+            .await
+            .unwrap();
+        let owner = Uuid::parse_str("5f0c3b9e-7d2a-4c1e-9b8f-0a1b2c3d4e5f").unwrap();
+        for content in ["Milk", "Bread"] {
+            sqlx::query("INSERT INTO Notes (id, owner, content, checked) VALUES (?, ?, ?, false)")
+                .bind(Uuid::new_v4())
+                .bind(owner)
+                .bind(content)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+
+        crate::db::migrator().run(&db).await.unwrap();
+
+        let users = UserRepository::new(db);
+        assert_eq!(
+            users
+                .find_by_identity(DEFAULT_PROVIDER, "5f0c3b9e-7d2a-4c1e-9b8f-0a1b2c3d4e5f")
+                .await
+                .unwrap(),
+            Some(UserId(owner))
+        );
+        assert_eq!(
+            users
+                .find_by_identity("other", "5f0c3b9e-7d2a-4c1e-9b8f-0a1b2c3d4e5f")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
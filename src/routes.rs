@@ -1,72 +1,235 @@
 use axum::routing::{delete, post, put};
-use axum::{Router, routing::get};
-use axum_login::{AuthManagerLayerBuilder, login_required};
-use std::path::Path;
-use tower_http::{services::ServeDir, trace::TraceLayer};
-use tower_sessions::{MemoryStore, SessionManagerLayer};
+use axum::{Extension, Router, extract::DefaultBodyLimit, middleware, routing::get};
+use axum_login::{AuthManagerLayerBuilder, login_required, permission_required};
+use std::sync::Arc;
+use tower::util::Either;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+    trace::TraceLayer,
+};
 use tracing::info;
 
+use crate::assets;
+use crate::auth;
 use crate::auth::login_datastar;
-use crate::{auth, layer};
-use crate::service::{OidcAuthBackend, OidcConfig};
+use crate::client_ip::{self, TrustedProxies};
+use crate::csrf::{self, CsrfConfig};
+use crate::headers::{self, SecurityHeaders};
+use crate::metrics;
+use crate::model::Role;
+use crate::rate_limit::{self, ClientRateLimiter, RateLimiter};
+use crate::replay::Replayer;
+use crate::service::OidcAuthBackend;
+use crate::session_cookie::{self, CookieProtection};
+use crate::shutdown;
 use crate::state::AppState;
-use crate::utils;
+use crate::tracing::request_span;
 use crate::view;
 
-pub async fn router() -> Router<AppState> {
-    let server_dir = utils::server_directory();
-    let dist_dir = Path::new(&server_dir).join("dist");
-
-    info!("Serve Path: {}", dist_dir.display());
-
-    let serve_dir = ServeDir::new(dist_dir)
-        .precompressed_br()
-        .precompressed_gzip();
-
-    // Session layer.
-    let session_store = MemoryStore::default();
-    let session_layer = SessionManagerLayer::new(session_store);
-
-    // Auth service.
-    let backend = OidcAuthBackend::new(OidcConfig {
-        client_id: std::env::var("OIDC_CLIENT_ID").expect("OIDC_CLIENT_ID must be set."),
-        client_secret: std::env::var("OIDC_CLIENT_SECRET")
-            .expect("OIDC_CLIENT_SECRET must be set."),
-        issuer_url: std::env::var("OIDC_ISSUER_URL").expect("OIDC_ISSUER_URL must be set."),
-        redirect_url: "http://127.0.0.1:3000/login/authorization/callback".to_string(),
-        scopes: vec![
-            "openid".to_string(),
-            "email".to_string(),
-            "profile".to_string(),
-            // offline access requires explicit consent
-            //"offline_access".to_string(),
-        ],
-    })
-    .await
-    .expect("Failed to create OIDC backend");
-
-    let auth_layer = AuthManagerLayerBuilder::new(backend, session_layer).build();
-
-    Router::new()
+/// Violation reports are small, anything larger isn't read.
+const CSP_REPORT_MAX_SIZE: usize = 64 * 1024;
+
+pub async fn router(state: &AppState) -> Router<AppState> {
+    let config = state.config();
+    let https = config.server.is_https();
+
+    // Session layer, sessions are kept in the database to survive restarts
+    let session_store = state.session_store().clone();
+    tokio::spawn(
+        session_store
+            .clone()
+            .continuously_delete_expired(config.session.cleanup_interval),
+    );
+
+    // Online backups of the database, `restore` brings the latest one back
+    if let Some(backups) = state.backups() {
+        tokio::spawn(backups.clone().continuously_backup());
+    }
+
+    let csrf_config = CsrfConfig::new(&config.server.base_url);
+    let cookie_config = state.session_cookie();
+
+    // Signing and encryption change the type of the cookie, and with it of the layers
+    let auth_layer = match cookie_config.protection() {
+        CookieProtection::Signed => Either::Left(
+            AuthManagerLayerBuilder::new(state.auth().clone(), cookie_config.signed(session_store))
+                .build(),
+        ),
+        CookieProtection::Encrypted => Either::Right(
+            AuthManagerLayerBuilder::new(
+                state.auth().clone(),
+                cookie_config.encrypted(session_store),
+            )
+            .build(),
+        ),
+    };
+
+    // Signing in is limited per client, changes are limited per user
+    let auth_limiter = ClientRateLimiter::new(
+        "auth rate limit",
+        config.rate_limits.auth,
+        config.rate_limits.unknown_clients,
+    );
+    let user_limiter = RateLimiter::new("user rate limit", config.rate_limits.user);
+    let limit_by_user =
+        || middleware::from_fn_with_state(user_limiter.clone(), rate_limit::limit_by_user);
+
+    // Every provider has its own callback route, the login request tells them apart
+    let callbacks = state
+        .auth()
+        .providers()
+        .iter()
+        .fold(Router::new(), |router, provider| {
+            router.route(&provider.callback_path, get(auth::login_callback))
+        });
+
+    // Routes that change notes, interrupted requests to them are replayed after a login
+    let note_routes = Router::new()
         .without_v07_checks()
-        .route("/", get(view::index::index))
         .route("/note", post(view::note::new_note))
         .route("/note/{id}", get(view::note::get_note))
         .route("/note/{id}", put(view::note::update_note))
         .route("/note/{id}", delete(view::note::delete_note))
         .route("/note/{id}/:edit", get(view::note::edit_note_view))
         .route("/note/{id}/:check", put(view::note::check_note))
-        .route("/note/{id}/:uncheck", put(view::note::uncheck_note))
-        .route_layer(login_required!(OidcAuthBackend, login_url = "/login"))
+        .route("/note/{id}/:uncheck", put(view::note::uncheck_note));
+
+    let replayer = Replayer::new(
+        note_routes
+            .clone()
+            .layer(CatchPanicLayer::new())
+            .with_state(state.clone()),
+    );
+
+    // Notes can also be used with a personal access token
+    let notes = Router::new()
+        .without_v07_checks()
+        .route("/", get(view::index::index))
+        .merge(note_routes)
+        .route_layer(limit_by_user())
+        .route_layer(middleware::from_fn(auth::require_user));
+
+    // Account management requires a signed in user
+    let account = Router::new()
+        .route("/account", get(view::account::account))
+        .route("/account/password", post(auth::change_password))
+        .route("/account/tokens", get(view::token::access_tokens))
+        .route("/account/tokens", post(view::token::create_token))
+        .route("/account/tokens/{id}", delete(view::token::revoke_token))
+        .route("/account/sessions", get(view::session::sessions))
+        .route(
+            "/account/sessions",
+            delete(view::session::revoke_other_sessions),
+        )
+        .route(
+            "/account/sessions/{id}",
+            delete(view::session::revoke_session),
+        )
+        .route("/login/{provider}/link", get(auth::link_provider))
+        .route_layer(limit_by_user())
+        .route_layer(login_required!(OidcAuthBackend, login_url = "/login"));
+
+    // Administration requires the admin role, which is granted by the OIDC provider
+    let admin = Router::new()
+        .without_v07_checks()
+        .route("/admin", get(view::admin::admin))
+        .route("/admin/users/{id}/:logout", post(view::admin::force_logout))
+        .route("/admin/users/{id}/:disable", put(view::admin::disable_user))
+        .route("/admin/users/{id}/:enable", put(view::admin::enable_user))
+        .route("/admin/csp-reports", delete(view::admin::clear_csp_reports))
+        .route("/admin/audit", get(view::audit::audit))
+        .route("/admin/audit/export", get(view::audit::export))
+        .route_layer(limit_by_user())
+        .route_layer(permission_required!(
+            OidcAuthBackend,
+            login_url = "/login",
+            Role::Admin
+        ));
+
+    // Each login starts an authentication request that is kept until the callback
+    let login = Router::new()
         .route("/login", get(auth::login))
         // Data-Star related routes for redirection
         .route("/login", put(login_datastar))
         .route("/login", post(login_datastar))
         .route("/login", delete(login_datastar))
-        .route("/login/authorization/callback", get(auth::login_callback))
+        .route("/login/password", post(auth::password_login))
+        .route("/login/{provider}", get(auth::login_provider))
+        .route("/signup", get(view::login::signup))
+        .route("/signup", post(auth::signup))
+        .merge(callbacks)
+        .route_layer(middleware::from_fn_with_state(
+            auth_limiter,
+            rate_limit::limit_by_ip,
+        ));
+
+    let router = Router::new()
+        .without_v07_checks()
+        .merge(notes)
+        .merge(account)
+        .merge(admin)
+        .merge(login)
         .route("/login/error", get(auth::login_error))
+        .layer(middleware::from_fn_with_state(csrf_config, csrf::protect))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::authenticate_token,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::audit_denials,
+        ))
+        .layer(middleware::from_fn(auth::track_activity))
         .layer(auth_layer)
-        .fallback_service(serve_dir)
-        .layer(layer::default_http_headers())
-        .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn_with_state(
+            cookie_config.clone(),
+            session_cookie::rotate_keys,
+        ))
+        .layer(Extension(replayer))
+        // Browsers send violation reports without cookies, so they bypass the CSRF layer
+        .route(
+            "/csp-report",
+            post(view::csp::csp_report).layer(DefaultBodyLimit::max(CSP_REPORT_MAX_SIZE)),
+        )
+        // Probes of the orchestrator don't authenticate
+        .route("/healthz", get(view::health::healthz))
+        .route("/readyz", get(view::health::readyz));
+
+    // Scrapers authenticate with a token or their address instead of a session
+    let router = if config.metrics.enabled {
+        router.route("/metrics", get(view::metrics::metrics))
+    } else {
+        router
+    };
+
+    // The frontend is embedded, serving it from disk picks up changes without a rebuild
+    let router = match &config.server.assets_dir {
+        Some(dir) => {
+            info!("Serving the frontend from {}", dir.display());
+            router.fallback_service(ServeDir::new(dir).precompressed_br().precompressed_gzip())
+        }
+        None => router.fallback(assets::serve_embedded),
+    };
+
+    router
+        .layer(middleware::from_fn_with_state(
+            state.shutdown().clone(),
+            shutdown::drain_streams,
+        ))
+        .layer(middleware::from_fn(metrics::track_requests))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(SecurityHeaders::new(&config.headers, https)),
+            headers::security_headers,
+        ))
+        // Everything inside sees the client address, not the one of a trusted proxy
+        .layer(middleware::from_fn_with_state(
+            TrustedProxies::new(&config.server.trusted_proxies),
+            client_ip::resolve_client_ip,
+        ))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        // The request id is set before the span is created, and returned to the client
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
 }
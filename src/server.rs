@@ -0,0 +1,243 @@
+use std::{
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use axum::{Router, http::Uri, response::Redirect};
+use axum_server::{Handle, tls_rustls::RustlsConfig};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{ServerConfig, seconds},
+    shutdown::Shutdown,
+};
+
+/// Time the connections have to close after the shutdown deadline.
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// HTTPS with a PEM certificate chain and private key, e.g. of an ACME client.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Interval between checks whether the files changed, they are reloaded if they did.
+    #[serde(default = "default_reload_interval", with = "seconds")]
+    pub reload_interval: Duration,
+    /// Address of a plain HTTP listener that redirects to `base_url`.
+    #[serde(default)]
+    pub redirect_from: Option<SocketAddr>,
+}
+
+fn default_reload_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl TlsConfig {
+    pub(crate) fn new(cert: PathBuf, key: PathBuf) -> Self {
+        Self {
+            cert,
+            key,
+            reload_interval: default_reload_interval(),
+            redirect_from: None,
+        }
+    }
+}
+
+/// Serves the application on the configured listener until the shutdown is complete.
+pub async fn serve(config: &ServerConfig, router: Router, shutdown: Shutdown) -> io::Result<()> {
+    if let Some(path) = &config.unix_socket {
+        return serve_unix(path, router, shutdown).await;
+    }
+
+    match &config.tls {
+        Some(tls) => serve_tls(config, tls, router, shutdown).await,
+        None => serve_tcp(config.bind, router, shutdown).await,
+    }
+}
+
+async fn serve_tcp(address: SocketAddr, router: Router, shutdown: Shutdown) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
+
+    // The peer address is shown on the sessions page
+    let server = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.draining());
+
+    until_closed(server.into_future(), &shutdown).await
+}
+
+async fn serve_tls(
+    config: &ServerConfig,
+    tls: &TlsConfig,
+    router: Router,
+    shutdown: Shutdown,
+) -> io::Result<()> {
+    let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
+    tokio::spawn(watch_certificates(rustls.clone(), tls.clone()));
+
+    if let Some(address) = tls.redirect_from {
+        tokio::spawn(redirect_to_https(
+            address,
+            config.base_url.clone(),
+            shutdown.clone(),
+        ));
+    }
+
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        let draining = shutdown.draining();
+        async move {
+            draining.await;
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    info!("Listening on https://{}", config.bind);
+
+    let server = axum_server::bind_rustls(config.bind, rustls)
+        .handle(handle)
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+
+    until_closed(server, &shutdown).await
+}
+
+/// Reloads the certificate once its files changed, e.g. after a renewal. A failed reload keeps
+/// the current certificate and is retried, as the files may not have been written completely.
+async fn watch_certificates(rustls: RustlsConfig, tls: TlsConfig) {
+    let mut loaded = modified(&tls).await;
+    let mut interval = tokio::time::interval(tls.reload_interval);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let current = modified(&tls).await;
+        if current == loaded {
+            continue;
+        }
+
+        match rustls.reload_from_pem_file(&tls.cert, &tls.key).await {
+            Ok(()) => {
+                info!("Reloaded the TLS certificate {}", tls.cert.display());
+                loaded = current;
+            }
+            Err(err) => warn!(
+                "Failed to reload the TLS certificate, keeping the current one: {}",
+                err
+            ),
+        }
+    }
+}
+
+async fn modified(tls: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let cert = tokio::fs::metadata(&tls.cert).await.ok()?.modified().ok()?;
+    let key = tokio::fs::metadata(&tls.key).await.ok()?.modified().ok()?;
+
+    Some((cert, key))
+}
+
+/// Redirects plain HTTP requests to the same path below the HTTPS base URL.
+async fn redirect_to_https(address: SocketAddr, base_url: String, shutdown: Shutdown) {
+    let base_url = base_url.trim_end_matches('/').to_string();
+    let redirect = move |uri: Uri| {
+        let location = format!(
+            "{}{}",
+            base_url,
+            uri.path_and_query().map_or("/", |path| path.as_str())
+        );
+        async move { Redirect::permanent(&location) }
+    };
+
+    let listener = match TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(
+                "Failed to listen on {} for HTTP redirects: {}",
+                address, err
+            );
+            return;
+        }
+    };
+    info!("Redirecting http://{} to HTTPS", address);
+
+    if let Err(err) = axum::serve(listener, Router::new().fallback(redirect))
+        .with_graceful_shutdown(shutdown.draining())
+        .await
+    {
+        error!("HTTP redirects failed: {}", err);
+    }
+}
+
+/// Listens on a Unix socket, for a reverse proxy on the same host. The client address is taken
+/// from the `Forwarded` or `X-Forwarded-For` header of the proxy.
+#[cfg(unix)]
+async fn serve_unix(path: &Path, router: Router, shutdown: Shutdown) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    use crate::client_ip::UnixConnectInfo;
+
+    // The socket of a previous run is left behind if it didn't shut down cleanly
+    if let Ok(metadata) = tokio::fs::symlink_metadata(path).await {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        tokio::fs::remove_file(path).await?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    info!("Listening on unix:{}", path.display());
+
+    // Marks the requests as coming from the socket, see `client_ip::resolve_client_ip`
+    let server = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<UnixConnectInfo>(),
+    )
+    .with_graceful_shutdown(shutdown.draining());
+    let result = until_closed(server.into_future(), &shutdown).await;
+
+    if let Err(err) = tokio::fs::remove_file(path).await {
+        warn!("Failed to remove the socket {}: {}", path.display(), err);
+    }
+
+    result
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_: &Path, _: Router, _: Shutdown) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+/// Runs the server until it stopped, or until the open connections had their chance to close
+/// after the shutdown deadline.
+async fn until_closed(
+    server: impl Future<Output = io::Result<()>>,
+    shutdown: &Shutdown,
+) -> io::Result<()> {
+    let deadline = async {
+        shutdown.closing().await;
+        tokio::time::sleep(CLOSE_GRACE).await;
+    };
+
+    tokio::select! {
+        result = server => result,
+        _ = deadline => {
+            warn!("Connections are still open, shutting down anyway");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use chrono::{TimeDelta, Utc};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    model::{AccessToken, AccessTokenId, TokenScope, UserId},
+    repository::{AccessTokenRepository, RepositoryError},
+};
+
+/// Prefix of all personal access tokens, makes them easy to spot in leaked secrets.
+const TOKEN_PREFIX: &str = "tdl_";
+
+/// `last_used_at` is only written if the stored value is older than this.
+const LAST_USED_RESOLUTION: TimeDelta = TimeDelta::minutes(1);
+
+/// Longest lifetime of a token, every token expires.
+const MAX_TOKEN_LIFETIME: TimeDelta = TimeDelta::days(365);
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum AccessTokenError {
+    #[error("access tokens must expire within a year, not after {0}")]
+    InvalidLifetime(TimeDelta),
+
+    #[error(transparent)]
+    RepositoryError(#[from] RepositoryError),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct AccessTokenService {
+    repository: AccessTokenRepository,
+}
+
+impl AccessTokenService {
+    pub(crate) fn new(repository: AccessTokenRepository) -> Self {
+        Self { repository }
+    }
+
+    /// Creates a new token and returns it together with its plain text value, which is not
+    /// stored anywhere.
+    pub async fn create_token(
+        &self,
+        user_id: UserId,
+        name: &str,
+        scopes: &[TokenScope],
+        expires_in: TimeDelta,
+    ) -> Result<(AccessToken, String), AccessTokenError> {
+        if expires_in <= TimeDelta::zero() || expires_in > MAX_TOKEN_LIFETIME {
+            return Err(AccessTokenError::InvalidLifetime(expires_in));
+        }
+
+        let secret = format!(
+            "{TOKEN_PREFIX}{}{}",
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        );
+        let now = Utc::now();
+
+        let token = AccessToken {
+            id: AccessTokenId::new_v4(),
+            user_id,
+            name: name.to_string(),
+            scopes: scopes
+                .iter()
+                .map(TokenScope::as_str)
+                .collect::<Vec<_>>()
+                .join(" "),
+            created_at: now,
+            expires_at: Some(now + expires_in),
+            last_used_at: None,
+        };
+
+        self.repository
+            .create(&token, hash_token(&secret).as_bytes())
+            .await?;
+
+        Ok((token, secret))
+    }
+
+    pub async fn get_tokens(&self, user_id: UserId) -> Result<Vec<AccessToken>, AccessTokenError> {
+        Ok(self.repository.find_all(user_id).await?)
+    }
+
+    pub async fn revoke_token(
+        &self,
+        user_id: UserId,
+        id: AccessTokenId,
+    ) -> Result<u64, AccessTokenError> {
+        Ok(self.repository.delete(user_id, id).await?)
+    }
+
+    /// Resolves the token presented in an `Authorization: Bearer` header, unknown and
+    /// expired tokens resolve to `None`.
+    pub async fn authenticate(
+        &self,
+        secret: &str,
+    ) -> Result<Option<AccessToken>, AccessTokenError> {
+        if !secret.starts_with(TOKEN_PREFIX) {
+            return Ok(None);
+        }
+
+        let Some(mut token) = self
+            .repository
+            .find_by_hash(hash_token(secret).as_bytes())
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        if token.is_expired(now) {
+            warn!("Expired access token {} was used", token.id);
+            return Ok(None);
+        }
+
+        if token
+            .last_used_at
+            .is_none_or(|last_used_at| last_used_at + LAST_USED_RESOLUTION < now)
+        {
+            self.repository.update_last_used(token.id, now).await?;
+
+            token.last_used_at = Some(now);
+        }
+
+        Ok(Some(token))
+    }
+}
+
+// Tokens are long random strings, a fast hash is enough to keep them out of the database
+fn hash_token(secret: &str) -> blake3::Hash {
+    blake3::hash(secret.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    async fn service() -> AccessTokenService {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::db::migrator().run(&db).await.unwrap();
+
+        AccessTokenService::new(AccessTokenRepository::new(db))
+    }
+
+    #[tokio::test]
+    async fn tokens_expire_within_a_year() {
+        let tokens = service().await;
+        let user_id = UserId(Uuid::new_v4());
+
+        for expires_in in [TimeDelta::zero(), TimeDelta::days(366)] {
+            assert!(matches!(
+                tokens
+                    .create_token(user_id, "ci", &[TokenScope::Read], expires_in)
+                    .await,
+                Err(AccessTokenError::InvalidLifetime(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use tracing::error;
+
+use crate::{
+    model::{AuditEntry, AuditFilter, AuditRecord},
+    repository::{AuditLogRepository, RepositoryError},
+};
+
+/// Entries listed in the admin console, the export contains all of them.
+const MAX_LISTED_ENTRIES: u32 = 500;
+
+#[derive(Debug, Clone)]
+pub(crate) struct AuditLogService {
+    repository: AuditLogRepository,
+}
+
+impl AuditLogService {
+    pub(crate) fn new(repository: AuditLogRepository) -> Self {
+        Self { repository }
+    }
+
+    /// Appends the event to the audit log. Failures are only logged, they must not fail the
+    /// request that caused the event.
+    pub async fn record(&self, record: AuditRecord) {
+        if let Err(error) = self.repository.append(&record).await {
+            error!(
+                "Failed to append {:?} to the audit log: {:?}",
+                record, error
+            );
+        }
+    }
+
+    pub async fn get_entries(
+        &self,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditEntry>, RepositoryError> {
+        self.repository.find(filter, Some(MAX_LISTED_ENTRIES)).await
+    }
+
+    /// All entries matching the filter, one JSON object per line.
+    pub async fn export(&self, filter: &AuditFilter) -> Result<String, RepositoryError> {
+        let entries = self.repository.find(filter, None).await?;
+
+        let mut lines = String::new();
+        for entry in entries {
+            let line = serde_json::to_string(&entry)?;
+
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+
+        Ok(lines)
+    }
+}
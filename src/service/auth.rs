@@ -1,45 +1,63 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use axum_login::{AuthnBackend, AuthzBackend};
-use chrono::{DateTime, TimeDelta, Utc};
+use chrono::{DateTime, Utc};
+use metrics::counter;
 use moka::future::Cache;
-use openid::{
-    Bearer, DiscoveredClient, StandardClaimsSubject, Token, TokenIntrospection,
-    error::StandardClaimsSubjectMissing,
-};
+use openid::{StandardClaimsSubject, Token};
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-use tracing::{info, instrument, warn};
+use tracing::{debug, info, instrument, warn};
 use uuid::Uuid;
 
-use crate::model::{PendingAction, SessionUser, UserId};
+use super::{
+    AuditLogService,
+    local_auth::{LocalAuthBackend, PasswordCredentials},
+    oidc::{CustomUserInfo, OidcProvider, OidcProviderConfig},
+};
+use crate::{
+    config::{Config, DEFAULT_PROVIDER},
+    model::{
+        AuditEvent, AuditRecord, LoginId, PendingAction, Role, SessionCredentials, SessionUser,
+        SessionUserId, User, UserId, UserIdentity, UserOverview,
+    },
+    repository::{RepositoryError, SessionRepository, UserRepository},
+};
 
-#[derive(Debug, Clone)]
+/// OpenID Connect providers users can sign in with.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub(crate) struct OidcConfig {
-    pub client_id: String,
-    pub client_secret: String,
-    pub issuer_url: String,
-    pub redirect_url: String,
-    pub scopes: Vec<String>,
+    pub providers: Vec<OidcProviderConfig>,
 }
 
-#[derive(Debug, Clone, Default)]
+/// How long a user has to sign in at the provider.
+const LOGIN_REQUEST_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone)]
 pub(crate) struct OidcState {
+    /// Provider the login request was sent to.
+    pub provider: String,
     pub action: Option<PendingAction>,
+    /// Link the identity to this user instead of signing in with it.
+    pub link_to: Option<UserId>,
 }
 
 #[derive(Clone)]
 pub(crate) struct OidcAuthBackend {
-    client: Arc<DiscoveredClient>,
-    scopes: String,
-
-    // In memory store of all the authenticated users
-    users: Cache<UserId, SessionUser>,
+    providers: Arc<Vec<OidcProvider>>,
+    local: Option<LocalAuthBackend>,
+    user_repository: UserRepository,
+    sessions: SessionRepository,
+    audit: AuditLogService,
+
+    // In memory cache of the authenticated users per login, backed by the session store
+    users: Cache<SessionUserId, SessionUser>,
     //users: Arc<RwLock<HashMap<UserId, SessionUser>>>,
 
-    // In memory story for in flight requests with additional state attached to it
-    login_requests: Arc<RwLock<HashMap<Uuid, OidcState>>>,
+    // In memory story for in flight requests with additional state attached to it, abandoned
+    // logins expire
+    login_requests: Cache<Uuid, OidcState>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -49,97 +67,347 @@ pub(crate) enum OidcError {
 }
 
 impl OidcAuthBackend {
-    pub async fn new(config: OidcConfig) -> Result<Self, OidcError> {
-        let issuer = reqwest::Url::parse(&config.issuer_url).unwrap();
+    pub async fn new(
+        config: &Config,
+        local: Option<LocalAuthBackend>,
+        user_repository: UserRepository,
+        sessions: SessionRepository,
+        audit: AuditLogService,
+    ) -> Result<Self, OidcError> {
+        let mut providers = Vec::with_capacity(config.oidc.providers.len());
+        for provider in &config.oidc.providers {
+            info!("Discovering OIDC provider {}", provider.id);
+            providers
+                .push(OidcProvider::discover(provider.clone(), &config.server.base_url).await?);
+        }
 
         Ok(Self {
-            login_requests: Arc::new(RwLock::new(HashMap::new())),
-            scopes: config.scopes.join(" "),
-            client: Arc::new(
-                DiscoveredClient::discover(
-                    config.client_id.to_owned(),
-                    config.client_secret.to_owned(),
-                    Some(config.redirect_url.to_owned()),
-                    issuer,
-                )
-                .await?,
-            ),
+            login_requests: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(LOGIN_REQUEST_TTL)
+                .name("login requests")
+                .build(),
+            providers: Arc::new(providers),
+            local,
+            user_repository,
+            sessions,
+            audit,
             users: Cache::builder()
                 .initial_capacity(100)
-                .max_capacity(64_000)
-                .time_to_idle(Duration::from_secs(15 * 60))
+                .max_capacity(config.session.cache_capacity)
+                .time_to_idle(config.session.cache_idle)
                 .name("user sessions")
                 .build(),
         })
     }
 
-    pub async fn get_authentication_url(&self, state: OidcState) -> String {
+    pub fn providers(&self) -> &[OidcProvider] {
+        &self.providers
+    }
+
+    /// Backend for local accounts, if they are enabled.
+    pub fn local(&self) -> Option<&LocalAuthBackend> {
+        self.local.as_ref()
+    }
+
+    pub fn provider(&self, id: &str) -> Option<&OidcProvider> {
+        self.providers.iter().find(|provider| provider.id == id)
+    }
+
+    pub async fn get_authentication_url(&self, state: OidcState) -> Result<String, AuthError> {
+        let provider = self
+            .provider(&state.provider)
+            .ok_or_else(|| AuthError::UnknownProvider(state.provider.clone()))?;
+
         let uuid = Uuid::new_v4();
-        self.login_requests.write().await.insert(uuid, state);
+        self.login_requests.insert(uuid, state).await;
 
-        let scopes = Some(self.scopes.as_str());
-        let state = uuid.to_string();
+        Ok(provider.auth_uri(uuid.to_string().as_str()).await)
+    }
 
-        let url = self.client.auth_uri(scopes, state.as_str());
-        url.to_string().to_owned()
+    pub async fn get_account(&self, user_id: UserId) -> Result<Option<User>, AuthError> {
+        Ok(self.user_repository.find_by_id(user_id).await?)
     }
-}
 
-#[derive(Debug, thiserror::Error)]
-pub(crate) enum AuthError {
-    #[error("unable to authenticate user")]
-    OidcPortalError(String),
-}
+    /// Creates a session for a local account and caches it like any other session.
+    pub async fn password_session(&self, user: &User) -> Result<SessionUser, AuthError> {
+        self.password_login(user, Uuid::new_v4()).await
+    }
 
-#[derive(Deserialize)]
-pub(crate) struct LoginCallback {
-    pub error: Option<String>,
-    pub error_description: Option<String>,
-    pub state: Option<String>,
-    pub code: Option<String>,
-    pub iss: Option<String>,
-}
+    /// Stores the session user of an existing login of a local account, e.g. after its password
+    /// changed, so the session keeps its login.
+    pub async fn password_login(
+        &self,
+        user: &User,
+        login_id: LoginId,
+    ) -> Result<SessionUser, AuthError> {
+        let local = self.local.as_ref().ok_or(AuthError::LocalAuthDisabled)?;
+        if user.disabled {
+            return Err(AuthError::AccountDisabled);
+        }
 
-#[derive(Deserialize)]
-pub(crate) enum AuthenticationCredentials {
-    LoginCallback(LoginCallback),
-}
+        let password = user
+            .password
+            .as_ref()
+            .ok_or(AuthError::InvalidCredentials)?;
+
+        let session_user = SessionUser {
+            id: user.id,
+            login_id,
+            credentials: SessionCredentials::Password,
+            auth_hash: blake3::hash(password.as_bytes()).as_bytes().to_vec(),
+            pending_action: None,
+            // Roles are managed at the OIDC providers, local accounts have none
+            roles: HashSet::new(),
+            expiration: local.session_duration(),
+            last_health_check: Utc::now(),
+        };
+
+        self.store_user(&session_user).await?;
+
+        Ok(session_user)
+    }
 
-// TODO: Create a typed struct
-#[derive(Debug, Deserialize, Serialize)]
-struct CustomUserInfo(HashMap<String, serde_json::Value>);
+    /// Takes the pending action of a freshly authenticated user, so it is only replayed once.
+    pub async fn take_pending_action(
+        &self,
+        user: &mut SessionUser,
+    ) -> Result<Option<PendingAction>, AuthError> {
+        let action = user.pending_action.take();
+        if action.is_some() {
+            self.store_user(user).await?;
+        }
 
-impl StandardClaimsSubject for CustomUserInfo {
-    fn sub(&self) -> Result<&str, StandardClaimsSubjectMissing> {
-        self.0
-            .get("sub")
-            .and_then(|x| x.as_str())
-            .ok_or(StandardClaimsSubjectMissing)
+        Ok(action)
     }
-}
 
-impl openid::CompactJson for CustomUserInfo {}
+    /// Persists the user of a session and caches it, so the session survives a restart.
+    async fn store_user(&self, user: &SessionUser) -> Result<(), AuthError> {
+        self.sessions.save_user(user).await?;
+        self.users.insert(user.key(), user.clone()).await;
 
-#[async_trait]
-impl AuthnBackend for OidcAuthBackend {
-    type User = SessionUser;
-    type Credentials = AuthenticationCredentials;
-    type Error = AuthError;
+        Ok(())
+    }
 
-    #[instrument(skip(self, creds))]
-    async fn authenticate(
+    /// Looks the user of a session up in the cache first, then in the session store.
+    async fn load_user(&self, id: SessionUserId) -> Result<Option<SessionUser>, AuthError> {
+        if let Some(user) = self.users.get(&id).await {
+            counter!("users_cache_requests_total", "result" => "hit").increment(1);
+            return Ok(Some(user));
+        }
+        counter!("users_cache_requests_total", "result" => "miss").increment(1);
+
+        // Disabled users are evicted from the cache, so checking the store is enough
+        if self.user_repository.is_disabled(id.user_id).await? {
+            return Ok(None);
+        }
+
+        let user = self.sessions.find_user(id).await?;
+        if let Some(user) = &user {
+            debug!(
+                "Restored session user {} from the session store",
+                id.user_id
+            );
+            self.users.insert(id, user.clone()).await;
+        }
+
+        Ok(user)
+    }
+
+    /// Ends a single login, the other sessions of the user stay signed in.
+    async fn forget_login(&self, id: SessionUserId) -> Result<(), AuthError> {
+        self.users.remove(&id).await;
+        self.sessions.delete_login(id).await?;
+
+        Ok(())
+    }
+
+    /// Ends every login of the user.
+    async fn forget_user(&self, user_id: UserId) -> Result<(), AuthError> {
+        let logins: Vec<SessionUserId> = self
+            .users
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| id.user_id == user_id)
+            .collect();
+        for id in logins {
+            self.users.remove(&id).await;
+        }
+        self.sessions.delete_user(user_id).await?;
+
+        Ok(())
+    }
+
+    /// Ends every login of the user except `keep`, along with their sessions.
+    pub async fn sign_out_other_logins(&self, keep: SessionUserId) -> Result<(), AuthError> {
+        let logins: Vec<SessionUserId> = self
+            .users
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| id.user_id == keep.user_id && *id != keep)
+            .collect();
+        for id in logins {
+            self.users.remove(&id).await;
+        }
+        self.sessions
+            .delete_other_logins(keep.user_id, keep.login_id)
+            .await?;
+
+        Ok(())
+    }
+
+    /// When the sign-in expires, for OIDC users that's when the access token does.
+    pub async fn get_session_expiry(
         &self,
-        creds: Self::Credentials,
-    ) -> Result<Option<Self::User>, Self::Error> {
+        id: SessionUserId,
+    ) -> Result<Option<DateTime<Utc>>, AuthError> {
+        Ok(self
+            .sessions
+            .find_user(id)
+            .await?
+            .map(|user| Utc::now() + user.expiration))
+    }
+
+    /// Lists all users for the admin console.
+    pub async fn get_user_overviews(&self) -> Result<Vec<UserOverview>, AuthError> {
+        Ok(self.user_repository.find_all_overviews().await?)
+    }
+
+    pub async fn get_user_overview(
+        &self,
+        user_id: UserId,
+    ) -> Result<Option<UserOverview>, AuthError> {
+        Ok(self.user_repository.find_overview(user_id).await?)
+    }
+
+    /// Number of users in the cache of authenticated users.
+    pub fn cached_users(&self) -> u64 {
+        self.users.entry_count()
+    }
+
+    /// Returns true if a login of the user is in the cache of authenticated users.
+    pub fn is_signed_in(&self, user_id: UserId) -> bool {
+        self.users.iter().any(|(id, _)| id.user_id == user_id)
+    }
+
+    /// Signs the user out of all of its sessions.
+    pub async fn force_logout(&self, user_id: UserId) -> Result<(), AuthError> {
+        self.forget_user(user_id).await?;
+        self.sessions.delete_all_by_user(user_id).await?;
+
+        info!("Signed out user {} everywhere", user_id);
+
+        Ok(())
+    }
+
+    /// Disabled users are signed out and can't sign in again until they are enabled.
+    pub async fn set_disabled(&self, user_id: UserId, disabled: bool) -> Result<(), AuthError> {
+        self.user_repository
+            .update_disabled(user_id, disabled)
+            .await?;
+
+        if disabled {
+            self.force_logout(user_id).await?;
+        }
+
+        info!("Set disabled of user {} to {}", user_id, disabled);
+
+        Ok(())
+    }
+
+    pub async fn get_identities(&self, user_id: UserId) -> Result<Vec<UserIdentity>, AuthError> {
+        Ok(self.user_repository.find_identities(user_id).await?)
+    }
+
+    /// Resolves the user an identity belongs to, new identities create a new user.
+    async fn resolve_user(
+        &self,
+        provider: &str,
+        subject: &str,
+        userinfo: &CustomUserInfo,
+    ) -> Result<UserId, AuthError> {
+        if let Some(user_id) = self
+            .user_repository
+            .find_by_identity(provider, subject)
+            .await?
+        {
+            return Ok(user_id);
+        }
+
+        // Users of the default provider used to be identified by their subject. The migration
+        // that created the users stored it in the canonical form, which the provider may not use.
+        if provider == DEFAULT_PROVIDER
+            && let Ok(uuid) = Uuid::parse_str(subject)
+            && let Some(user_id) = self
+                .user_repository
+                .find_by_identity(provider, &uuid.to_string())
+                .await?
+        {
+            return Ok(user_id);
+        }
+
+        let user_id = UserId(Uuid::new_v4());
+
+        info!(
+            "Creating user {} for identity {}@{}",
+            user_id, subject, provider
+        );
+
+        self.user_repository
+            .create_with_identity(
+                user_id,
+                userinfo.username().unwrap_or(subject),
+                provider,
+                subject,
+            )
+            .await?;
+
+        Ok(user_id)
+    }
+
+    /// Links an identity to an existing user, identities can only belong to a single user.
+    async fn link_identity(
+        &self,
+        user_id: UserId,
+        provider: &str,
+        subject: &str,
+    ) -> Result<UserId, AuthError> {
+        match self
+            .user_repository
+            .find_by_identity(provider, subject)
+            .await?
+        {
+            Some(owner) if owner == user_id => Ok(user_id),
+            Some(_) => Err(AuthError::IdentityAlreadyLinked),
+            None => {
+                info!(
+                    "Linking identity {}@{} to user {}",
+                    subject, provider, user_id
+                );
+
+                self.user_repository
+                    .add_identity(user_id, provider, subject)
+                    .await?;
+
+                Ok(user_id)
+            }
+        }
+    }
+
+    /// Signs the user in with the credentials, see [`AuthnBackend::authenticate`].
+    async fn authenticate_credentials(
+        &self,
+        creds: AuthenticationCredentials,
+    ) -> Result<Option<SessionUser>, AuthError> {
         match creds {
             AuthenticationCredentials::LoginCallback(callback) => {
-                if callback.iss.is_none() {
+                let Some(iss) = callback.iss else {
                     warn!("OIDC callback missing 'iss' field");
 
                     return Err(AuthError::OidcPortalError(
                         "OIDC callback missing 'iss' field".to_string(),
                     ));
-                }
+                };
 
                 if let Some(error) = callback.error {
                     warn!(
@@ -152,14 +420,12 @@ impl AuthnBackend for OidcAuthBackend {
                     return Err(AuthError::OidcPortalError(error));
                 }
 
-                if callback.state.is_none() {
+                let Some(state) = callback.state else {
                     warn!("OIDC callback missing 'state' field");
                     return Err(AuthError::OidcPortalError(
                         "OIDC callback missing 'state' field".to_string(),
                     ));
-                }
-
-                let state = callback.state.unwrap();
+                };
 
                 if let Some(code) = callback.code {
                     let state = Uuid::parse_str(&state).unwrap_or_else(|_| {
@@ -167,7 +433,7 @@ impl AuthnBackend for OidcAuthBackend {
                         Uuid::new_v4()
                     });
 
-                    let state = match self.login_requests.write().await.remove(&state) {
+                    let state = match self.login_requests.remove(&state).await {
                         Some(state) => state,
                         None => {
                             warn!("State not found in login requests: {}", state);
@@ -175,12 +441,33 @@ impl AuthnBackend for OidcAuthBackend {
                         }
                     };
 
-                    let bearer = self.client.request_token(&code).await.unwrap();
+                    let provider = self
+                        .provider(&state.provider)
+                        .ok_or_else(|| AuthError::UnknownProvider(state.provider.clone()))?;
+
+                    // Guard against mix-up attacks, the response must come from the provider
+                    // the login request was sent to
+                    if !provider.is_issuer(&iss).await {
+                        warn!("OIDC callback from unexpected issuer: {}", iss);
+                        return Err(AuthError::OidcPortalError(
+                            "OIDC callback from unexpected issuer".to_string(),
+                        ));
+                    }
+
+                    let client = provider.client().await;
+                    let bearer = client
+                        .request_token(&code)
+                        .await
+                        .map_err(|error| AuthError::TokenRequestFailed(error.to_string()))?;
                     let mut token: Token = bearer.into();
 
                     if let Some(id_token) = token.id_token.as_mut() {
-                        self.client.decode_token(id_token).unwrap();
-                        self.client.validate_token(id_token, None, None).unwrap();
+                        client
+                            .decode_token(id_token)
+                            .map_err(|error| AuthError::InvalidIdToken(error.to_string()))?;
+                        client
+                            .validate_token(id_token, None, None)
+                            .map_err(|error| AuthError::InvalidIdToken(error.to_string()))?;
                     } else {
                         warn!("Failed validation, no id_token found");
                         return Err(AuthError::OidcPortalError(
@@ -188,108 +475,155 @@ impl AuthnBackend for OidcAuthBackend {
                         ));
                     }
 
-                    let userinfo: CustomUserInfo =
-                        self.client.request_userinfo_custom(&token).await.unwrap();
-
-                    // Verify token vis introspection since no JWT:
-                    let introspection: TokenIntrospection<CustomUserInfo> = self
-                        .client
-                        .request_token_introspection(&token)
+                    let userinfo: CustomUserInfo = client
+                        .request_userinfo_custom(&token)
                         .await
-                        .unwrap();
+                        .map_err(|error| AuthError::UserinfoFailed(error.to_string()))?;
 
-                    let expiration =
-                        DateTime::from_timestamp(introspection.exp.unwrap(), 0).unwrap();
+                    // JWT access tokens are validated locally, everything else via introspection
+                    let (access_token_kind, expiration) = provider
+                        .check_new_access_token(&token.bearer.access_token)
+                        .await?;
                     let now = Utc::now();
+                    let expiration = (expiration - now).to_std().map_err(|_| {
+                        AuthError::InvalidAccessToken(
+                            "the access token already expired".to_string(),
+                        )
+                    })?;
+
+                    let subject = userinfo.sub().map_err(|_| {
+                        AuthError::OidcPortalError("userinfo is missing 'sub'".to_string())
+                    })?;
+
+                    let user_id = match state.link_to {
+                        Some(user_id) => self.link_identity(user_id, &provider.id, subject).await?,
+                        None => self.resolve_user(&provider.id, subject, &userinfo).await?,
+                    };
 
-                    if introspection.active == false && expiration > now {
-                        return Err(AuthError::OidcPortalError(
-                            "User is not active or token has expired".to_string(),
-                        ));
+                    if self.user_repository.is_disabled(user_id).await? {
+                        warn!("Disabled user {} tried to sign in", user_id);
+                        return Err(AuthError::AccountDisabled);
                     }
 
-                    // TODO: User ID should something be like blake3(iss + sub) to ensure users of multiple OIDC providers don't collide
-                    let user_id = UserId(Uuid::parse_str(userinfo.sub().unwrap()).unwrap_or_else(
-                        |_| {
-                            warn!("Invalid sub in userinfo: {}", userinfo.sub().unwrap());
-                            Uuid::new_v4()
-                        },
-                    ));
-
                     let session_user = SessionUser {
                         id: user_id,
-                        access_token_hash: blake3::hash(token.bearer.access_token.as_bytes())
+                        login_id: Uuid::new_v4(),
+                        auth_hash: blake3::hash(token.bearer.access_token.as_bytes())
                             .as_bytes()
                             .to_vec(),
-                        access_token: token.bearer.access_token,
+                        credentials: SessionCredentials::Oidc {
+                            provider: provider.id.clone(),
+                            access_token: token.bearer.access_token,
+                            access_token_kind,
+                        },
                         pending_action: state.action,
-                        expiration: (expiration - now).to_std().unwrap(),
+                        roles: provider.roles(&userinfo),
+                        expiration,
                         last_health_check: now,
                     };
 
-                    self.users.insert(user_id, session_user.clone()).await;
+                    self.store_user(&session_user).await?;
 
                     return Ok(Some(session_user));
                 }
 
                 tracing::warn!("Invalid OIDC flow!");
-                return Err(AuthError::OidcPortalError("Invalid OIDC flow!".to_string()));
+                Err(AuthError::OidcPortalError("Invalid OIDC flow!".to_string()))
+            }
+            AuthenticationCredentials::Password(credentials) => {
+                let local = self.local.as_ref().ok_or(AuthError::LocalAuthDisabled)?;
+
+                match local.authenticate(credentials).await? {
+                    Some(user) => Ok(Some(self.password_session(&user).await?)),
+                    None => {
+                        warn!("Invalid username or password");
+                        Ok(None)
+                    }
+                }
             }
         }
     }
 
-    #[instrument(skip(self))]
-    async fn get_user(&self, user_id: &UserId) -> Result<Option<Self::User>, Self::Error> {
-        let user = self.users.get(user_id).await;
+    /// Loads the user of a session and checks its access token when it is due.
+    async fn check_session_user(
+        &self,
+        id: &SessionUserId,
+    ) -> Result<Option<SessionUser>, AuthError> {
+        let user = self.load_user(*id).await?;
         if let Some(user) = user {
-            let now = Utc::now();
+            let SessionCredentials::Oidc {
+                provider,
+                access_token,
+                access_token_kind,
+            } = &user.credentials
+            else {
+                // Password sessions have no token that could be revoked elsewhere
+                return Ok(Some(user));
+            };
 
-            if (user.last_health_check + TimeDelta::seconds(10)) < now {
-                info!("Performing token introspection");
-
-                let token: Token = Bearer {
-                    access_token: user.access_token.clone(),
-                    token_type: "bearer".to_string(),
-                    scope: None,
-                    state: None,
-                    refresh_token: None,
-                    expires_in: None,
-                    id_token: None,
-                    extra: None,
-                }
-                .into();
-
-                let introspection: TokenIntrospection<CustomUserInfo> = self
-                    .client
-                    .request_token_introspection(&token)
+            let now = Utc::now();
+            let Some(provider) = self.provider(provider) else {
+                warn!("Provider {} of user is no longer configured", provider);
+                self.forget_login(*id).await?;
+                self.audit
+                    .record(
+                        AuditRecord::new(AuditEvent::SessionInvalidated)
+                            .user(id.user_id)
+                            .details(format!("provider {provider} is no longer configured")),
+                    )
+                    .await;
+
+                return Ok(None);
+            };
+
+            if (user.last_health_check + provider.health_check_interval(*access_token_kind)) < now {
+                info!(
+                    "Performing {:?} access token health check",
+                    access_token_kind
+                );
+
+                match provider
+                    .check_access_token(access_token, *access_token_kind)
                     .await
-                    .unwrap();
-
-                info!("introspection: {:?}", introspection);
-
-                let expiration = DateTime::from_timestamp(introspection.exp.unwrap(), 0).unwrap();
-
-                if introspection.active == false && expiration > now {
-                    warn!("Oidc introspection resulted in invalid token for user!");
-                    let _ = self.users.remove(user_id).await;
-
-                    Err(AuthError::OidcPortalError(
-                        "User is not active or token has expired".to_string(),
-                    ))
-                } else {
-                    // Update cache
-                    let new_user = SessionUser {
-                        id: user.id,
-                        access_token: user.access_token,
-                        access_token_hash: user.access_token_hash,
-                        pending_action: user.pending_action,
-                        expiration: user.expiration,
-                        last_health_check: now,
-                    };
+                {
+                    // The provider may be back on the next request, the token is only dropped
+                    // once it is known to be invalid or expired
+                    Err(error)
+                        if error.is_transient()
+                            && now < user.last_health_check + user.expiration =>
+                    {
+                        warn!(
+                            "Access token health check failed, keeping the session: {}",
+                            error
+                        );
+                        counter!("get_user_errors_total", "reason" => error.reason()).increment(1);
+
+                        Ok(Some(user))
+                    }
+                    Err(error) => {
+                        warn!("Access token health check failed for user: {}", error);
+                        self.forget_login(*id).await?;
+                        self.audit
+                            .record(
+                                AuditRecord::new(AuditEvent::SessionInvalidated)
+                                    .user(id.user_id)
+                                    .details(&error),
+                            )
+                            .await;
+
+                        Err(error)
+                    }
+                    Ok(expiration) => {
+                        let new_user = SessionUser {
+                            expiration: (expiration - now).to_std().unwrap_or_default(),
+                            last_health_check: now,
+                            ..user
+                        };
 
-                    self.users.insert(*user_id, new_user.clone()).await;
+                        self.store_user(&new_user).await?;
 
-                    Ok(Some(new_user))
+                        Ok(Some(new_user))
+                    }
                 }
             } else {
                 Ok(Some(user))
@@ -300,7 +634,179 @@ impl AuthnBackend for OidcAuthBackend {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum AuthError {
+    #[error("unable to authenticate user")]
+    OidcPortalError(String),
+
+    #[error("token request failed: {0}")]
+    TokenRequestFailed(String),
+
+    #[error("ID token rejected: {0}")]
+    InvalidIdToken(String),
+
+    #[error("userinfo request failed: {0}")]
+    UserinfoFailed(String),
+
+    #[error("access token rejected: {0}")]
+    InvalidAccessToken(String),
+
+    #[error("token introspection failed: {0}")]
+    IntrospectionFailed(String),
+
+    #[error("unable to download JWKS: {0}")]
+    JwksUnavailable(String),
+
+    #[error("unable to download the discovery document: {0}")]
+    DiscoveryFailed(String),
+
+    #[error("unknown OIDC provider {0}")]
+    UnknownProvider(String),
+
+    #[error("identity is already linked to another user")]
+    IdentityAlreadyLinked,
+
+    #[error("invalid username or password")]
+    InvalidCredentials,
+
+    #[error("this account is disabled")]
+    AccountDisabled,
+
+    #[error("local accounts are disabled")]
+    LocalAuthDisabled,
+
+    #[error("signing up is disabled")]
+    SignupDisabled,
+
+    #[error("the username must not be empty")]
+    InvalidUsername,
+
+    #[error("the username is already taken")]
+    UsernameTaken,
+
+    #[error("the password must be at least {0} characters long")]
+    WeakPassword(usize),
+
+    #[error(transparent)]
+    RepositoryError(#[from] RepositoryError),
+}
+
+impl AuthError {
+    /// Whether the provider couldn't be reached, rather than rejecting the token.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::IntrospectionFailed(_) | Self::JwksUnavailable(_) | Self::DiscoveryFailed(_)
+        )
+    }
+
+    /// Short name of the error, used as metric label.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::OidcPortalError(_) => "oidc_portal_error",
+            Self::TokenRequestFailed(_) => "token_request_failed",
+            Self::InvalidIdToken(_) => "invalid_id_token",
+            Self::UserinfoFailed(_) => "userinfo_failed",
+            Self::InvalidAccessToken(_) => "invalid_access_token",
+            Self::IntrospectionFailed(_) => "introspection_failed",
+            Self::JwksUnavailable(_) => "jwks_unavailable",
+            Self::DiscoveryFailed(_) => "discovery_failed",
+            Self::UnknownProvider(_) => "unknown_provider",
+            Self::IdentityAlreadyLinked => "identity_already_linked",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::AccountDisabled => "account_disabled",
+            Self::LocalAuthDisabled => "local_auth_disabled",
+            Self::SignupDisabled => "signup_disabled",
+            Self::InvalidUsername => "invalid_username",
+            Self::UsernameTaken => "username_taken",
+            Self::WeakPassword(_) => "weak_password",
+            Self::RepositoryError(_) => "repository_error",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LoginCallback {
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+    pub state: Option<String>,
+    pub code: Option<String>,
+    pub iss: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) enum AuthenticationCredentials {
+    LoginCallback(LoginCallback),
+    Password(PasswordCredentials),
+}
+
+impl AuthenticationCredentials {
+    fn method(&self) -> &'static str {
+        match self {
+            Self::LoginCallback(_) => "oidc",
+            Self::Password(_) => "password",
+        }
+    }
+}
+
+#[async_trait]
+impl AuthnBackend for OidcAuthBackend {
+    type User = SessionUser;
+    type Credentials = AuthenticationCredentials;
+    type Error = AuthError;
+
+    #[instrument(skip(self, creds))]
+    async fn authenticate(
+        &self,
+        creds: Self::Credentials,
+    ) -> Result<Option<Self::User>, Self::Error> {
+        let method = creds.method();
+        let result = self.authenticate_credentials(creds).await;
+
+        match &result {
+            Ok(Some(_)) => {
+                counter!("authentications_total", "method" => method, "result" => "success")
+                    .increment(1);
+            }
+            Ok(None) => {
+                counter!(
+                    "authentications_total",
+                    "method" => method,
+                    "result" => "failure",
+                    "reason" => AuthError::InvalidCredentials.reason()
+                )
+                .increment(1);
+            }
+            Err(error) => {
+                counter!(
+                    "authentications_total",
+                    "method" => method,
+                    "result" => "failure",
+                    "reason" => error.reason()
+                )
+                .increment(1);
+            }
+        }
+
+        result
+    }
+
+    #[instrument(skip(self))]
+    async fn get_user(&self, id: &SessionUserId) -> Result<Option<Self::User>, Self::Error> {
+        self.check_session_user(id).await.inspect_err(|error| {
+            counter!("get_user_errors_total", "reason" => error.reason()).increment(1);
+        })
+    }
+}
+
 #[async_trait]
 impl AuthzBackend for OidcAuthBackend {
-    type Permission = String;
+    type Permission = Role;
+
+    async fn get_user_permissions(
+        &self,
+        user: &Self::User,
+    ) -> Result<HashSet<Self::Permission>, Self::Error> {
+        Ok(user.roles.clone())
+    }
 }
@@ -0,0 +1,274 @@
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::seconds,
+    repository::{MaintenanceRepository, RepositoryError},
+};
+
+/// Backups are named `backup-<timestamp>.db`, other files in the directory are left alone.
+const BACKUP_PREFIX: &str = "backup-";
+const BACKUP_EXTENSION: &str = ".db";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Periodic online backups of the SQLite database into a directory.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BackupConfig {
+    pub dir: PathBuf,
+    #[serde(default = "default_interval", with = "seconds")]
+    pub interval: Duration,
+    /// Number of most recent backups that are kept.
+    #[serde(default = "default_keep_last")]
+    pub keep_last: usize,
+    /// Number of days of which the last backup is kept, in addition to `keep_last`.
+    #[serde(default = "default_keep_daily")]
+    pub keep_daily: usize,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+fn default_keep_last() -> usize {
+    24
+}
+
+fn default_keep_daily() -> usize {
+    7
+}
+
+impl BackupConfig {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            interval: default_interval(),
+            keep_last: default_keep_last(),
+            keep_daily: default_keep_daily(),
+        }
+    }
+}
+
+/// A backup in the backup directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Backup {
+    pub created_at: DateTime<Utc>,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum BackupError {
+    #[error("unable to prepare the backup directory: {0}")]
+    Directory(#[source] io::Error),
+
+    #[error("unable to write the backup: {0}")]
+    Write(#[from] RepositoryError),
+
+    #[error("unable to rename the backup: {0}")]
+    Rename(#[source] io::Error),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BackupService {
+    repository: MaintenanceRepository,
+    config: BackupConfig,
+}
+
+impl BackupService {
+    pub(crate) fn new(repository: MaintenanceRepository, config: BackupConfig) -> Self {
+        Self { repository, config }
+    }
+
+    /// Writes a backup, then deletes the backups the retention rules don't keep.
+    pub async fn backup(&self) -> Result<PathBuf, BackupError> {
+        let path = self.write_backup().await.inspect_err(|_| {
+            counter!("db_backups_total", "result" => "error").increment(1);
+        })?;
+        counter!("db_backups_total", "result" => "ok").increment(1);
+        info!("Wrote a backup to {}", path.display());
+
+        self.prune().await;
+        Ok(path)
+    }
+
+    async fn write_backup(&self) -> Result<PathBuf, BackupError> {
+        tokio::fs::create_dir_all(&self.config.dir)
+            .await
+            .map_err(BackupError::Directory)?;
+
+        let name = format!(
+            "{}{}{}",
+            BACKUP_PREFIX,
+            Utc::now().format(TIMESTAMP_FORMAT),
+            BACKUP_EXTENSION
+        );
+        let path = self.config.dir.join(&name);
+        // Written under another name first, so an interrupted backup is never restored
+        let partial = self.config.dir.join(format!("{name}.partial"));
+
+        // `VACUUM INTO` doesn't overwrite the leftover of an interrupted backup
+        if tokio::fs::try_exists(&partial).await.unwrap_or(false) {
+            tokio::fs::remove_file(&partial)
+                .await
+                .map_err(BackupError::Directory)?;
+        }
+
+        self.repository.backup(&partial).await?;
+        tokio::fs::rename(&partial, &path)
+            .await
+            .map_err(BackupError::Rename)?;
+
+        Ok(path)
+    }
+
+    async fn prune(&self) {
+        let backups = match list_backups(&self.config.dir).await {
+            Ok(backups) => backups,
+            Err(error) => {
+                warn!("Failed to list the backups: {}", error);
+                return;
+            }
+        };
+
+        let retained = retained(&backups, self.config.keep_last, self.config.keep_daily);
+        for backup in backups
+            .iter()
+            .filter(|backup| !retained.contains(&backup.path))
+        {
+            match tokio::fs::remove_file(&backup.path).await {
+                Ok(()) => info!("Deleted the backup {}", backup.path.display()),
+                Err(error) => warn!(
+                    "Failed to delete the backup {}: {}",
+                    backup.path.display(),
+                    error
+                ),
+            }
+        }
+    }
+
+    /// Writes a backup every `interval`, runs until the server stops.
+    pub async fn continuously_backup(self) {
+        let mut interval = tokio::time::interval(self.config.interval);
+        // Restarts don't produce a backup each
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            // Errors are logged and counted, the next attempt may succeed
+            if let Err(error) = self.backup().await {
+                error!("Failed to write a backup: {}", error);
+            }
+        }
+    }
+}
+
+/// Backups in the directory, the most recent first.
+pub(crate) async fn list_backups(dir: &Path) -> io::Result<Vec<Backup>> {
+    let mut backups = Vec::new();
+
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let created_at = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(BACKUP_PREFIX))
+            .and_then(|name| name.strip_suffix(BACKUP_EXTENSION))
+            .and_then(|timestamp| NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok());
+
+        if let Some(created_at) = created_at {
+            backups.push(Backup {
+                created_at: created_at.and_utc(),
+                path: entry.path(),
+            });
+        }
+    }
+
+    backups.sort_by_key(|backup| Reverse(backup.created_at));
+    Ok(backups)
+}
+
+/// The `keep_last` most recent backups, and the last backup of each of the `keep_daily` most
+/// recent days with a backup. `backups` must be sorted with the most recent first.
+fn retained(backups: &[Backup], keep_last: usize, keep_daily: usize) -> HashSet<PathBuf> {
+    let mut retained: HashSet<PathBuf> = backups
+        .iter()
+        .take(keep_last)
+        .map(|backup| backup.path.clone())
+        .collect();
+
+    let mut days = HashSet::new();
+    for backup in backups {
+        let day = backup.created_at.date_naive();
+        if days.contains(&day) {
+            continue;
+        }
+        if days.len() == keep_daily {
+            break;
+        }
+        days.insert(day);
+        retained.insert(backup.path.clone());
+    }
+
+    retained
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// Two backups a day at 06:00 and 18:00, the most recent first.
+    fn backups(days: u32) -> Vec<Backup> {
+        (1..=days)
+            .rev()
+            .flat_map(|day| [18, 6].map(|hour| (day, hour)))
+            .map(|(day, hour)| Backup {
+                created_at: Utc.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap(),
+                path: PathBuf::from(format!("backup-202603{day:02}T{hour:02}0000Z.db")),
+            })
+            .collect()
+    }
+
+    fn paths(backups: &[Backup], indexes: &[usize]) -> HashSet<PathBuf> {
+        indexes
+            .iter()
+            .map(|&index| backups[index].path.clone())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_most_recent() {
+        let backups = backups(3);
+        assert_eq!(retained(&backups, 3, 0), paths(&backups, &[0, 1, 2]));
+    }
+
+    #[test]
+    fn keeps_the_last_backup_of_each_day() {
+        let backups = backups(4);
+        assert_eq!(retained(&backups, 0, 3), paths(&backups, &[0, 2, 4]));
+    }
+
+    #[test]
+    fn combines_both_rules() {
+        let backups = backups(4);
+        assert_eq!(retained(&backups, 2, 3), paths(&backups, &[0, 1, 2, 4]));
+    }
+
+    #[test]
+    fn keeps_everything_if_there_are_fewer_backups() {
+        let backups = backups(2);
+        assert_eq!(retained(&backups, 24, 7), paths(&backups, &[0, 1, 2, 3]));
+    }
+}
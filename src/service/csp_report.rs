@@ -0,0 +1,100 @@
+use std::net::IpAddr;
+
+use chrono::Utc;
+use tracing::warn;
+
+use crate::{
+    model::{CspReport, CspViolation},
+    rate_limit::{ClientRateLimiter, RateLimit},
+    repository::{CspReportRepository, RepositoryError},
+};
+
+/// Reports a single client may send, further ones are dropped.
+const RATE_LIMIT: RateLimit = RateLimit {
+    per_minute: 20,
+    burst: 20,
+};
+
+/// Reports all clients without a known address may send together.
+const UNKNOWN_CLIENTS_RATE_LIMIT: RateLimit = RateLimit {
+    per_minute: 60,
+    burst: 60,
+};
+
+/// Violations taken from a single report, browsers batch them with the Reporting API.
+const MAX_VIOLATIONS_PER_REPORT: usize = 10;
+
+/// Distinct violations that are kept, the least recently reported are deleted first.
+const MAX_STORED_REPORTS: u32 = 500;
+
+/// Reported values are cut to this many characters, they are chosen by the client.
+const MAX_FIELD_LENGTH: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) struct CspReportService {
+    repository: CspReportRepository,
+    reporters: ClientRateLimiter,
+}
+
+impl CspReportService {
+    pub(crate) fn new(repository: CspReportRepository) -> Self {
+        Self {
+            repository,
+            reporters: ClientRateLimiter::new(
+                "csp reporters",
+                RATE_LIMIT,
+                UNKNOWN_CLIENTS_RATE_LIMIT,
+            ),
+        }
+    }
+
+    /// Stores the violations of a report, unless the client exceeded its rate limit.
+    pub async fn report(
+        &self,
+        client: Option<IpAddr>,
+        user_agent: Option<&str>,
+        violations: Vec<CspViolation>,
+    ) -> Result<(), RepositoryError> {
+        if self.reporters.check(client).is_err() {
+            warn!(
+                "Dropped CSP report of {}, rate limit exceeded",
+                client.map_or("unknown clients".to_string(), |ip| ip.to_string())
+            );
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        let user_agent = user_agent.map(truncate);
+
+        for violation in violations.into_iter().take(MAX_VIOLATIONS_PER_REPORT) {
+            let violation = CspViolation {
+                document_uri: truncate(&violation.document_uri),
+                directive: truncate(&violation.directive),
+                blocked_uri: truncate(&violation.blocked_uri),
+                source_file: truncate(&violation.source_file),
+                line_number: violation.line_number,
+                disposition: truncate(&violation.disposition),
+                sample: violation.sample.as_deref().map(truncate),
+            };
+
+            self.repository
+                .record(&violation, user_agent.as_deref(), now)
+                .await?;
+        }
+
+        self.repository.delete_oldest(MAX_STORED_REPORTS).await?;
+        Ok(())
+    }
+
+    pub async fn get_reports(&self) -> Result<Vec<CspReport>, RepositoryError> {
+        self.repository.find_recent(MAX_STORED_REPORTS).await
+    }
+
+    pub async fn clear_reports(&self) -> Result<u64, RepositoryError> {
+        self.repository.delete_all().await
+    }
+}
+
+fn truncate(value: &str) -> String {
+    value.chars().take(MAX_FIELD_LENGTH).collect()
+}
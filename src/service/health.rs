@@ -0,0 +1,139 @@
+use std::{collections::BTreeMap, fmt::Display, future::Future, time::Duration};
+
+use moka::future::Cache;
+use serde::Serialize;
+use tokio::time::{Instant, timeout};
+use tracing::warn;
+
+use crate::{repository::MaintenanceRepository, service::OidcAuthBackend, shutdown::Shutdown};
+
+/// Checks that take longer fail, so a hanging dependency doesn't hang the probe.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Providers are checked at most this often, however often the probe is called.
+const PROVIDER_CHECK_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CheckStatus {
+    Ok,
+    Fail,
+}
+
+/// Outcome of a single readiness check, the error is only logged.
+#[derive(Debug, Clone)]
+pub(crate) struct Check {
+    pub status: CheckStatus,
+    pub latency_ms: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct HealthService {
+    repository: MaintenanceRepository,
+    auth: OidcAuthBackend,
+    shutdown: Shutdown,
+    provider_checks: Cache<String, Check>,
+}
+
+impl HealthService {
+    pub(crate) fn new(
+        repository: MaintenanceRepository,
+        auth: OidcAuthBackend,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            repository,
+            auth,
+            shutdown,
+            provider_checks: Cache::builder()
+                .time_to_live(PROVIDER_CHECK_TTL)
+                .name("provider checks")
+                .build(),
+        }
+    }
+
+    /// Checks the database, its migrations and the reachability of every OIDC provider. A
+    /// shutting down instance isn't ready, so load balancers stop sending it requests. It is
+    /// only ready if all checks passed.
+    pub async fn readiness(&self) -> CheckStatus {
+        let (database, migrations) = tokio::join!(
+            measure(self.repository.ping()),
+            measure(async {
+                match self.repository.outdated_migrations().await {
+                    Ok(outdated) if outdated.is_empty() => Ok(()),
+                    Ok(outdated) => Err(outdated
+                        .iter()
+                        .map(|(version, _, state)| format!("{version} is {state}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")),
+                    Err(error) => Err(error.to_string()),
+                }
+            })
+        );
+
+        let mut checks = BTreeMap::new();
+        checks.insert(
+            "shutdown".to_string(),
+            measure(async {
+                if self.shutdown.is_draining() {
+                    Err("the server is shutting down")
+                } else {
+                    Ok(())
+                }
+            })
+            .await,
+        );
+        checks.insert("database".to_string(), database);
+        checks.insert("migrations".to_string(), migrations);
+
+        // Concurrent probes wait for the same check instead of each asking the provider
+        for provider in self.auth.providers() {
+            let check = self
+                .provider_checks
+                .get_with(provider.id.clone(), measure(provider.check_reachable()))
+                .await;
+            checks.insert(format!("oidc:{}", provider.id), check);
+        }
+
+        let status = if checks.values().all(|check| check.status == CheckStatus::Ok) {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Fail
+        };
+
+        for (name, check) in &checks {
+            if let Some(error) = &check.error {
+                warn!(
+                    "Readiness check {} failed after {:.0}ms: {}",
+                    name, check.latency_ms, error
+                );
+            }
+        }
+
+        status
+    }
+}
+
+/// Runs the check with a timeout and measures how long it took.
+async fn measure<E: Display>(check: impl Future<Output = Result<(), E>>) -> Check {
+    let start = Instant::now();
+    let result = timeout(CHECK_TIMEOUT, check).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(error)) => Some(error.to_string()),
+        Err(_) => Some(format!("timed out after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+
+    Check {
+        status: if error.is_none() {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Fail
+        },
+        latency_ms,
+        error,
+    }
+}
@@ -0,0 +1,180 @@
+use std::{sync::LazyLock, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use super::auth::AuthError;
+use crate::{
+    config::seconds,
+    model::{User, UserId},
+    repository::UserRepository,
+};
+
+/// Minimum number of characters of a local password.
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+// Verified against when the username is unknown, so both cases take the same time
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| password_auth::generate_hash("dummy"));
+
+/// Local accounts with username and password, next to or instead of OIDC providers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LocalAuthConfig {
+    pub enabled: bool,
+    /// Allow everyone to create a local account.
+    pub signup: bool,
+    /// How long a password session lasts.
+    #[serde(with = "seconds")]
+    pub session_duration: Duration,
+}
+
+impl Default for LocalAuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            signup: true,
+            session_duration: Duration::from_secs(8 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PasswordCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Username and password authentication against the argon2 hashes in the users table.
+#[derive(Debug, Clone)]
+pub(crate) struct LocalAuthBackend {
+    config: LocalAuthConfig,
+    user_repository: UserRepository,
+}
+
+impl LocalAuthBackend {
+    pub fn new(config: LocalAuthConfig, user_repository: UserRepository) -> Self {
+        Self {
+            config,
+            user_repository,
+        }
+    }
+
+    pub fn signup_enabled(&self) -> bool {
+        self.config.signup
+    }
+
+    pub fn session_duration(&self) -> Duration {
+        self.config.session_duration
+    }
+
+    #[instrument(skip(self, credentials), fields(username = %credentials.username))]
+    pub async fn authenticate(
+        &self,
+        credentials: PasswordCredentials,
+    ) -> Result<Option<User>, AuthError> {
+        let user = self
+            .user_repository
+            .find_local_by_username(&credentials.username)
+            .await?;
+
+        let hash = user
+            .as_ref()
+            .and_then(|user| user.password.clone())
+            .unwrap_or_else(|| DUMMY_HASH.clone());
+
+        if verify_password(credentials.password, hash).await {
+            Ok(user)
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[instrument(skip(self, password))]
+    pub async fn signup(&self, username: &str, password: String) -> Result<User, AuthError> {
+        if !self.config.signup {
+            return Err(AuthError::SignupDisabled);
+        }
+
+        let username = username.trim();
+        if username.is_empty() {
+            return Err(AuthError::InvalidUsername);
+        }
+
+        check_password_strength(&password)?;
+
+        if self
+            .user_repository
+            .find_local_by_username(username)
+            .await?
+            .is_some()
+        {
+            return Err(AuthError::UsernameTaken);
+        }
+
+        let user = User {
+            id: UserId(Uuid::new_v4()),
+            username: username.to_string(),
+            password: Some(hash_password(password).await),
+            disabled: false,
+        };
+
+        self.user_repository
+            .create_local(user.id, &user.username, user.password.as_deref().unwrap())
+            .await?;
+
+        info!("Created local account {}", user.id);
+
+        Ok(user)
+    }
+
+    #[instrument(skip(self, current_password, new_password))]
+    pub async fn change_password(
+        &self,
+        user_id: UserId,
+        current_password: String,
+        new_password: String,
+    ) -> Result<User, AuthError> {
+        let mut user = self
+            .user_repository
+            .find_by_id(user_id)
+            .await?
+            .filter(|user| user.password.is_some())
+            .ok_or(AuthError::InvalidCredentials)?;
+
+        if !verify_password(current_password, user.password.clone().unwrap()).await {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        check_password_strength(&new_password)?;
+
+        let hash = hash_password(new_password).await;
+        self.user_repository.update_password(user_id, &hash).await?;
+        user.password = Some(hash);
+
+        info!("Changed password of local account {}", user.id);
+
+        Ok(user)
+    }
+}
+
+fn check_password_strength(password: &str) -> Result<(), AuthError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AuthError::WeakPassword(MIN_PASSWORD_LENGTH));
+    }
+
+    Ok(())
+}
+
+// Argon2 is deliberately slow, keep it away from the async runtime
+pub(crate) async fn hash_password(password: String) -> String {
+    tokio::task::spawn_blocking(move || password_auth::generate_hash(password))
+        .await
+        .expect("Password hashing panicked")
+}
+
+async fn verify_password(password: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || password_auth::verify_password(password, &hash).is_ok())
+        .await
+        .expect("Password verification panicked")
+}
@@ -1,6 +1,26 @@
+mod access_token;
+mod audit_log;
 mod auth;
+mod backup;
+mod csp_report;
+mod health;
+mod local_auth;
 mod note;
+mod oidc;
+mod session;
 
+pub(crate) use access_token::{AccessTokenError, AccessTokenService};
+pub(crate) use audit_log::AuditLogService;
+pub(crate) use backup::{BackupConfig, BackupError, BackupService, list_backups};
+pub(crate) use csp_report::CspReportService;
+pub(crate) use health::{CheckStatus, HealthService};
 pub(crate) use note::NoteService;
+pub(crate) use session::SessionService;
 
-pub(crate) use auth::{AuthenticationCredentials, LoginCallback, OidcAuthBackend, OidcConfig, OidcState};
+pub(crate) use auth::{
+    AuthError, AuthenticationCredentials, LoginCallback, OidcAuthBackend, OidcConfig, OidcState,
+};
+pub(crate) use local_auth::{
+    LocalAuthBackend, LocalAuthConfig, PasswordCredentials, hash_password,
+};
+pub(crate) use oidc::{OidcProvider, OidcProviderConfig};
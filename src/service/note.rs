@@ -1,46 +1,45 @@
-use tracing::error;
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{TimeDelta, Utc};
+use metrics::counter;
 
 use crate::{
     model::{Note, NoteId, UserId},
-    repository::NoteRepository,
+    repository::{NoteStorage, RepositoryError},
 };
 
 #[derive(Debug, Clone)]
 pub(crate) struct NoteService {
-    repository: NoteRepository,
+    repository: Arc<dyn NoteStorage>,
 }
 
-// TODO: Improve error handling
 impl NoteService {
-    pub(crate) fn new(repository: NoteRepository) -> Self {
+    pub(crate) fn new(repository: Arc<dyn NoteStorage>) -> Self {
         Self { repository }
     }
 
-    pub async fn create_note(&self, user_id: UserId, content: &str) -> Result<Note, ()> {
-        self.repository
-            .create(user_id, content)
-            .await
-            .map_err(|error| error!("Failed to create note: {:?}", error))
-            .map(|id| Note {
-                id,
-                owner: user_id,
-                content: content.to_string(),
-                checked: false,
-            })
+    pub async fn create_note(
+        &self,
+        user_id: UserId,
+        content: &str,
+    ) -> Result<Note, RepositoryError> {
+        let id = self.repository.create(user_id, content).await?;
+        counter!("notes_created_total").increment(1);
+
+        Ok(Note {
+            id,
+            owner: user_id,
+            content: content.to_string(),
+            checked: false,
+        })
     }
 
-    pub async fn get_note(&self, user_id: UserId, id: NoteId) -> Result<Note, ()> {
-        self.repository
-            .find_by_id(user_id, id)
-            .await
-            .map_err(|error| error!("Failed to get note: {:?}", error))
+    pub async fn get_note(&self, user_id: UserId, id: NoteId) -> Result<Note, RepositoryError> {
+        self.repository.find_by_id(user_id, id).await
     }
 
-    pub async fn get_notes(&self, user_id: UserId) -> Result<Vec<Note>, ()> {
-        self.repository
-            .find_all(user_id)
-            .await
-            .map_err(|error| error!("Failed to get notes: {:?}", error))
+    pub async fn get_notes(&self, user_id: UserId) -> Result<Vec<Note>, RepositoryError> {
+        self.repository.find_all(user_id).await
     }
 
     pub async fn update_note_content(
@@ -48,14 +47,10 @@ impl NoteService {
         user_id: UserId,
         id: NoteId,
         content: &str,
-    ) -> Result<Note, ()> {
-        self.repository
-            .update_content(user_id, id, content)
-            .await
-            .map_err(|error| error!("Failed to update note: {:?}", error))
-            .unwrap();
-
-        return self.get_note(user_id, id).await;
+    ) -> Result<Note, RepositoryError> {
+        self.repository.update_content(user_id, id, content).await?;
+
+        self.get_note(user_id, id).await
     }
 
     pub async fn update_note_checked(
@@ -63,20 +58,27 @@ impl NoteService {
         user_id: UserId,
         id: NoteId,
         checked: bool,
-    ) -> Result<Note, ()> {
-        self.repository
-            .update_checked(user_id, id, checked)
-            .await
-            .map_err(|error| error!("Failed to update note: {:?}", error))
-            .unwrap();
-
-        return self.get_note(user_id, id).await;
+    ) -> Result<Note, RepositoryError> {
+        self.repository.update_checked(user_id, id, checked).await?;
+        counter!("notes_checked_total", "checked" => checked.to_string()).increment(1);
+
+        self.get_note(user_id, id).await
+    }
+
+    pub async fn delete_note(&self, user_id: UserId, id: NoteId) -> Result<u64, RepositoryError> {
+        let deleted = self.repository.delete(user_id, id).await?;
+        counter!("notes_deleted_total").increment(deleted);
+
+        Ok(deleted)
+    }
+
+    /// Number of notes per user, for the admin console.
+    pub async fn count_notes(&self) -> Result<HashMap<UserId, i64>, RepositoryError> {
+        self.repository.count_by_owner().await
     }
 
-    pub async fn delete_note(&self, user_id: UserId, id: NoteId) -> Result<u64, ()> {
-        self.repository
-            .delete(user_id, id)
-            .await
-            .map_err(|error| error!("Failed to delete note: {:?}", error))
+    /// Removes the notes that have been in the trash for longer than `retention` for good.
+    pub async fn purge_trash(&self, retention: TimeDelta) -> Result<u64, RepositoryError> {
+        self.repository.purge_deleted(Utc::now() - retention).await
     }
 }
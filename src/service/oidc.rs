@@ -0,0 +1,457 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use biscuit::{
+    ClaimPresenceOptions, ClaimsSet, Empty, Presence, Validation, ValidationOptions, jwk::JWKSet,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use metrics::{counter, histogram};
+use openid::{
+    Bearer, DiscoveredClient, IdToken, Jws, StandardClaimsSubject, Token, TokenIntrospection,
+    error::{Decode, StandardClaimsSubjectMissing},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{debug, info, instrument, warn};
+
+use super::auth::{AuthError, OidcError};
+use crate::{
+    config::seconds,
+    model::{AccessTokenKind, Role},
+};
+
+/// Minimum time between two JWKS downloads, also when they are triggered by an unknown key id.
+const JWKS_MIN_REFRESH_INTERVAL: TimeDelta = TimeDelta::seconds(60);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct OidcProviderConfig {
+    /// Identifier used in URLs and to link identities to users.
+    pub id: String,
+    /// Name shown on the provider chooser, defaults to the id.
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub issuer_url: String,
+    /// Path of the callback route, relative to the base URL of the application. Defaults to
+    /// `/login/{id}/callback`.
+    pub callback_path: String,
+    pub scopes: Vec<String>,
+    pub health_check: HealthCheckConfig,
+    pub roles: RoleConfig,
+}
+
+impl Default for OidcProviderConfig {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            issuer_url: String::new(),
+            callback_path: String::new(),
+            scopes: vec![
+                "openid".to_string(),
+                "email".to_string(),
+                "profile".to_string(),
+                // offline access requires explicit consent
+                //"offline_access".to_string(),
+            ],
+            health_check: HealthCheckConfig::default(),
+            roles: RoleConfig::default(),
+        }
+    }
+}
+
+/// Maps the groups or roles of a user at the provider to application roles. The provider
+/// usually only releases the claim if the matching scope, e.g. `groups`, is requested.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RoleConfig {
+    /// Userinfo claim that lists the groups or roles of the user.
+    pub claim: String,
+    /// Values of the claim and the role they grant.
+    pub mapping: BTreeMap<String, Role>,
+}
+
+impl Default for RoleConfig {
+    fn default() -> Self {
+        Self {
+            claim: "groups".to_string(),
+            mapping: BTreeMap::from([("admin".to_string(), Role::Admin)]),
+        }
+    }
+}
+
+/// Controls how often the access tokens of authenticated users are re-validated.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct HealthCheckConfig {
+    /// Validate JWT access tokens locally against the JWKS of the provider.
+    pub local_validation: bool,
+    /// Expected `aud` claim of JWT access tokens, defaults to the client id.
+    pub audience: Option<String>,
+    /// Interval between local validations of JWT access tokens.
+    #[serde(with = "seconds")]
+    pub validation_interval: Duration,
+    /// Interval between introspection requests for opaque access tokens.
+    #[serde(with = "seconds")]
+    pub introspection_interval: Duration,
+    /// Maximum age of the cached JWKS before it is downloaded again.
+    #[serde(with = "seconds")]
+    pub jwks_max_age: Duration,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            local_validation: true,
+            audience: None,
+            validation_interval: Duration::from_secs(10),
+            introspection_interval: Duration::from_secs(5 * 60),
+            jwks_max_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+// TODO: Create a typed struct
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct CustomUserInfo(HashMap<String, serde_json::Value>);
+
+impl CustomUserInfo {
+    /// Human readable name of the user, falls back to the subject.
+    pub fn username(&self) -> Option<&str> {
+        ["preferred_username", "email", "name", "sub"]
+            .iter()
+            .find_map(|claim| self.0.get(*claim).and_then(|value| value.as_str()))
+    }
+
+    /// Values of a claim that is either a single string or a list of strings.
+    pub fn claim_values(&self, claim: &str) -> Vec<&str> {
+        match self.0.get(claim) {
+            Some(serde_json::Value::String(value)) => vec![value.as_str()],
+            Some(serde_json::Value::Array(values)) => {
+                values.iter().filter_map(|value| value.as_str()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl StandardClaimsSubject for CustomUserInfo {
+    fn sub(&self) -> Result<&str, StandardClaimsSubjectMissing> {
+        self.0
+            .get("sub")
+            .and_then(|x| x.as_str())
+            .ok_or(StandardClaimsSubjectMissing)
+    }
+}
+
+impl openid::CompactJson for CustomUserInfo {}
+
+/// Discovered client together with the time its JWKS were downloaded.
+#[derive(Debug, Clone)]
+struct ProviderClient {
+    client: Arc<DiscoveredClient>,
+    jwks_fetched_at: DateTime<Utc>,
+}
+
+/// A single OpenID Connect provider users can sign in with.
+#[derive(Debug)]
+pub(crate) struct OidcProvider {
+    pub id: String,
+    pub name: String,
+    pub callback_path: String,
+    scopes: String,
+    audience: String,
+    health_check: HealthCheckConfig,
+    roles: RoleConfig,
+
+    // Replaced whenever the JWKS of the provider are refreshed
+    client: RwLock<ProviderClient>,
+}
+
+impl OidcProvider {
+    pub async fn discover(config: OidcProviderConfig, base_url: &str) -> Result<Self, OidcError> {
+        let issuer = reqwest::Url::parse(&config.issuer_url).unwrap();
+        let redirect_url = format!("{}{}", base_url.trim_end_matches('/'), config.callback_path);
+
+        let client = DiscoveredClient::discover(
+            config.client_id.to_owned(),
+            config.client_secret.to_owned(),
+            Some(redirect_url),
+            issuer,
+        )
+        .await?;
+
+        Ok(Self {
+            scopes: config.scopes.join(" "),
+            audience: config
+                .health_check
+                .audience
+                .clone()
+                .unwrap_or_else(|| config.client_id.clone()),
+            health_check: config.health_check,
+            roles: config.roles,
+            id: config.id,
+            name: config.name,
+            callback_path: config.callback_path,
+            client: RwLock::new(ProviderClient {
+                client: Arc::new(client),
+                jwks_fetched_at: Utc::now(),
+            }),
+        })
+    }
+
+    pub async fn auth_uri(&self, state: &str) -> String {
+        let scopes = Some(self.scopes.as_str());
+
+        let url = self.client().await.auth_uri(scopes, state);
+        url.to_string().to_owned()
+    }
+
+    /// Returns true if `iss` denotes the issuer of this provider.
+    pub async fn is_issuer(&self, iss: &str) -> bool {
+        reqwest::Url::parse(iss).ok().as_ref() == Some(&self.client().await.config().issuer)
+    }
+
+    /// Returns the discovered client, the JWKS are refreshed if they exceeded their maximum age.
+    pub async fn client(&self) -> Arc<DiscoveredClient> {
+        let provider = self.client.read().await.clone();
+        let max_age = TimeDelta::from_std(self.health_check.jwks_max_age).unwrap_or(TimeDelta::MAX);
+
+        if provider.jwks_fetched_at + max_age > Utc::now() {
+            return provider.client;
+        }
+
+        match self.refresh_jwks().await {
+            Ok(client) => client,
+            Err(error) => {
+                warn!("Failed to refresh JWKS, using cached keys: {}", error);
+                provider.client
+            }
+        }
+    }
+
+    /// Downloads the discovery document and the JWKS, to tell whether the provider is reachable.
+    pub async fn check_reachable(&self) -> Result<(), AuthError> {
+        let client = self.client().await;
+
+        let mut discovery_url = client.config().issuer.clone();
+        discovery_url
+            .path_segments_mut()
+            .map_err(|_| AuthError::DiscoveryFailed("invalid issuer URL".to_string()))?
+            .pop_if_empty()
+            .extend([".well-known", "openid-configuration"]);
+
+        client
+            .http_client
+            .get(discovery_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| AuthError::DiscoveryFailed(error.to_string()))?;
+
+        client
+            .http_client
+            .get(client.config().jwks_uri.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| AuthError::JwksUnavailable(error.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Downloads the JWKS of the provider and swaps them into the client.
+    #[instrument(skip(self), fields(provider = %self.id))]
+    async fn refresh_jwks(&self) -> Result<Arc<DiscoveredClient>, AuthError> {
+        let mut provider = self.client.write().await;
+
+        // Requests that waited for the lock use the keys another one just downloaded
+        if provider.jwks_fetched_at + JWKS_MIN_REFRESH_INTERVAL > Utc::now() {
+            return Ok(provider.client.clone());
+        }
+
+        let jwks: JWKSet<Empty> = provider
+            .client
+            .http_client
+            .get(provider.client.config().jwks_uri.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| AuthError::JwksUnavailable(error.to_string()))?
+            .json()
+            .await
+            .map_err(|error| AuthError::JwksUnavailable(error.to_string()))?;
+
+        let mut client = provider.client.as_ref().clone();
+        client.jwks = Some(jwks);
+
+        provider.client = Arc::new(client);
+        provider.jwks_fetched_at = Utc::now();
+
+        info!("Refreshed JWKS of the OIDC provider");
+
+        Ok(provider.client.clone())
+    }
+
+    /// Validates the signature and the `exp`, `nbf`, `iss` and `aud` claims of a JWT access
+    /// token against the cached JWKS and returns its expiration time.
+    async fn validate_access_token(&self, access_token: &str) -> Result<DateTime<Utc>, AuthError> {
+        let mut client = self.client().await;
+        let mut token: IdToken<ClaimsSet<Empty>> = Jws::new_encoded(access_token);
+
+        if let Err(openid::error::Error::Decode(Decode::MissingKey(kid))) =
+            client.decode_token(&mut token)
+        {
+            // The provider might have rotated its signing keys since the last download
+            let fetched_at = self.client.read().await.jwks_fetched_at;
+            if fetched_at + JWKS_MIN_REFRESH_INTERVAL > Utc::now() {
+                return Err(AuthError::InvalidAccessToken(format!(
+                    "unknown key id {kid}"
+                )));
+            }
+
+            debug!("Unknown key id {} in access token, refreshing JWKS", kid);
+            client = self.refresh_jwks().await?;
+        }
+
+        client
+            .decode_token(&mut token)
+            .map_err(|error| AuthError::InvalidAccessToken(error.to_string()))?;
+
+        let claims = token
+            .payload()
+            .map_err(|error| AuthError::InvalidAccessToken(error.to_string()))?;
+
+        claims
+            .registered
+            .validate(ValidationOptions {
+                claim_presence_options: ClaimPresenceOptions {
+                    expiry: Presence::Required,
+                    issuer: Presence::Required,
+                    audience: Presence::Required,
+                    ..Default::default()
+                },
+                audience: Validation::Validate(self.audience.clone()),
+                ..Default::default()
+            })
+            .map_err(|error| AuthError::InvalidAccessToken(error.to_string()))?;
+
+        // Compare as URLs, the same way the id token issuer is validated
+        let issuer = claims.registered.issuer.as_deref().unwrap_or_default();
+        if reqwest::Url::parse(issuer).ok().as_ref() != Some(&client.config().issuer) {
+            return Err(AuthError::InvalidAccessToken(format!(
+                "unexpected issuer {issuer}"
+            )));
+        }
+
+        Ok(*claims.registered.expiry.unwrap())
+    }
+
+    /// Asks the introspection endpoint of the provider about the access token and returns its
+    /// expiration time.
+    async fn introspect_access_token(
+        &self,
+        access_token: &str,
+    ) -> Result<DateTime<Utc>, AuthError> {
+        let token: Token = Bearer {
+            access_token: access_token.to_string(),
+            token_type: "bearer".to_string(),
+            scope: None,
+            state: None,
+            refresh_token: None,
+            expires_in: None,
+            id_token: None,
+            extra: None,
+        }
+        .into();
+
+        let client = self.client().await;
+        let start = Instant::now();
+        let result = client.request_token_introspection(&token).await;
+        histogram!("oidc_introspection_duration_seconds", "provider" => self.id.clone())
+            .record(start.elapsed().as_secs_f64());
+
+        let introspection: TokenIntrospection<CustomUserInfo> = result.map_err(|error| {
+            counter!("oidc_introspection_errors_total", "provider" => self.id.clone()).increment(1);
+            AuthError::IntrospectionFailed(error.to_string())
+        })?;
+
+        debug!("introspection: {:?}", introspection);
+
+        let expiration = introspection
+            .exp
+            .and_then(|exp| DateTime::from_timestamp(exp, 0))
+            .unwrap_or_default();
+
+        if !introspection.active || expiration <= Utc::now() {
+            return Err(AuthError::InvalidAccessToken(
+                "User is not active or token has expired".to_string(),
+            ));
+        }
+
+        Ok(expiration)
+    }
+
+    /// Validates the access token with the strategy that fits its kind.
+    pub async fn check_access_token(
+        &self,
+        access_token: &str,
+        kind: AccessTokenKind,
+    ) -> Result<DateTime<Utc>, AuthError> {
+        match kind {
+            AccessTokenKind::Jwt => self.validate_access_token(access_token).await,
+            AccessTokenKind::Opaque => self.introspect_access_token(access_token).await,
+        }
+    }
+
+    /// Application roles granted by the groups or roles claim of the user.
+    pub fn roles(&self, userinfo: &CustomUserInfo) -> HashSet<Role> {
+        let values = userinfo.claim_values(&self.roles.claim);
+
+        self.roles
+            .mapping
+            .iter()
+            .filter(|(group, _)| values.contains(&group.as_str()))
+            .map(|(_, role)| *role)
+            .collect()
+    }
+
+    /// Checks a new access token and returns how it is checked from now on, with its expiration
+    /// time. Tokens that look like a JWT but can't be validated locally, e.g. because they are
+    /// meant for another audience or use another algorithm, are introspected instead.
+    pub async fn check_new_access_token(
+        &self,
+        access_token: &str,
+    ) -> Result<(AccessTokenKind, DateTime<Utc>), AuthError> {
+        if self.health_check.local_validation && access_token.split('.').count() == 3 {
+            match self.validate_access_token(access_token).await {
+                Ok(expiration) => return Ok((AccessTokenKind::Jwt, expiration)),
+                Err(AuthError::InvalidAccessToken(error)) => {
+                    debug!(
+                        "Introspecting the access token, it can't be validated: {}",
+                        error
+                    )
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        let expiration = self.introspect_access_token(access_token).await?;
+        Ok((AccessTokenKind::Opaque, expiration))
+    }
+
+    pub fn health_check_interval(&self, kind: AccessTokenKind) -> TimeDelta {
+        let interval = match kind {
+            AccessTokenKind::Jwt => self.health_check.validation_interval,
+            AccessTokenKind::Opaque => self.health_check.introspection_interval,
+        };
+
+        TimeDelta::from_std(interval).unwrap_or(TimeDelta::MAX)
+    }
+}
@@ -0,0 +1,37 @@
+use crate::{
+    model::{ActiveSession, UserId},
+    repository::{RepositoryError, SessionRepository},
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct SessionService {
+    repository: SessionRepository,
+}
+
+impl SessionService {
+    pub(crate) fn new(repository: SessionRepository) -> Self {
+        Self { repository }
+    }
+
+    pub async fn get_sessions(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<ActiveSession>, RepositoryError> {
+        self.repository.find_by_user(user_id).await
+    }
+
+    pub async fn revoke_session(&self, user_id: UserId, id: &str) -> Result<u64, RepositoryError> {
+        self.repository.delete_by_user(user_id, id).await
+    }
+
+    /// Signs out every session of the user, except the one with id `current`.
+    pub async fn revoke_other_sessions(
+        &self,
+        user_id: UserId,
+        current: &str,
+    ) -> Result<u64, RepositoryError> {
+        self.repository
+            .delete_others_by_user(user_id, current)
+            .await
+    }
+}
@@ -0,0 +1,386 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        HeaderValue,
+        header::{COOKIE, SET_COOKIE},
+    },
+    middleware::Next,
+    response::Response,
+};
+use chacha20poly1305::{
+    AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, OsRng},
+};
+use tower_sessions::{
+    Expiry, SessionManagerLayer, SessionStore,
+    cookie::{Cookie, CookieJar, Key, SameSite, time},
+    service::{PrivateCookie, SignedCookie},
+};
+use tracing::{debug, warn};
+
+use crate::config::SessionConfig;
+
+/// How the session id in the cookie is protected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CookieProtection {
+    /// The id is readable but can't be changed without the key.
+    Signed,
+    /// The id is encrypted and authenticated.
+    Encrypted,
+}
+
+/// Session cookie settings and keys.
+#[derive(Clone)]
+pub(crate) struct SessionCookieConfig {
+    name: String,
+    domain: Option<String>,
+    path: String,
+    /// Sessions end after this long without a request, without it when the browser closes.
+    expiry: Option<time::Duration>,
+    secure: bool,
+    http_only: bool,
+    protection: CookieProtection,
+    /// Key that new cookies are signed or encrypted with.
+    key: Key,
+    /// Keys of a previous rotation, cookies of them are accepted and replaced.
+    previous_keys: Vec<Key>,
+}
+
+impl SessionCookieConfig {
+    pub fn new(config: &SessionConfig, https: bool) -> Self {
+        let key = match &config.secret {
+            Some(secret) => parse_key(secret),
+            None => {
+                warn!("No session secret is configured, sessions don't survive a restart");
+                Key::generate()
+            }
+        };
+
+        let protection = if config.encrypted {
+            CookieProtection::Encrypted
+        } else {
+            CookieProtection::Signed
+        };
+
+        Self {
+            name: config.cookie_name.clone(),
+            domain: config.cookie_domain.clone(),
+            path: config.cookie_path.clone(),
+            expiry: Some(config.expiry)
+                .filter(|expiry| !expiry.is_zero())
+                .map(|expiry| time::Duration::seconds(expiry.as_secs() as i64)),
+            secure: config.cookie_secure.unwrap_or(https),
+            http_only: config.cookie_http_only,
+            protection,
+            key,
+            previous_keys: config
+                .previous_secrets
+                .iter()
+                .map(|secret| parse_key(secret))
+                .collect(),
+        }
+    }
+
+    pub fn protection(&self) -> CookieProtection {
+        self.protection
+    }
+
+    /// Cipher of the credentials kept with the sessions, derived from the same keys.
+    pub fn credentials_cipher(&self) -> CredentialsCipher {
+        CredentialsCipher {
+            keys: std::iter::once(&self.key)
+                .chain(&self.previous_keys)
+                .map(|key| blake3::derive_key(CREDENTIALS_KEY_CONTEXT, key.master()))
+                .collect(),
+        }
+    }
+
+    /// Session layer with the cookie settings, signing or encryption is added by the caller as
+    /// it changes the type of the layer.
+    pub fn layer<S: SessionStore>(&self, store: S) -> SessionManagerLayer<S> {
+        // Lax keeps the session when the OIDC provider redirects back, CSRF is checked separately
+        let mut layer = SessionManagerLayer::new(store)
+            .with_name(self.name.clone())
+            .with_path(self.path.clone())
+            .with_same_site(SameSite::Lax)
+            .with_secure(self.secure)
+            .with_http_only(self.http_only);
+
+        if let Some(domain) = &self.domain {
+            layer = layer.with_domain(domain.clone());
+        }
+        if let Some(expiry) = self.expiry {
+            layer = layer.with_expiry(Expiry::OnInactivity(expiry));
+        }
+
+        layer
+    }
+
+    pub fn signed<S: SessionStore>(&self, store: S) -> SessionManagerLayer<S, SignedCookie> {
+        self.layer(store).with_signed(self.key.clone())
+    }
+
+    pub fn encrypted<S: SessionStore>(&self, store: S) -> SessionManagerLayer<S, PrivateCookie> {
+        self.layer(store).with_private(self.key.clone())
+    }
+
+    /// Returns the session id of the cookie if it was signed or encrypted with `key`.
+    fn open(&self, key: &Key, cookie: Cookie<'static>) -> Option<String> {
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie);
+
+        let cookie = match self.protection {
+            CookieProtection::Signed => jar.signed(key).get(&self.name),
+            CookieProtection::Encrypted => jar.private(key).get(&self.name),
+        };
+        cookie.map(|cookie| cookie.value().to_string())
+    }
+
+    /// Signs or encrypts the session id with the current key.
+    fn seal(&self, session_id: String) -> String {
+        let mut jar = CookieJar::new();
+        let cookie = Cookie::new(self.name.clone(), session_id);
+
+        match self.protection {
+            CookieProtection::Signed => jar.signed_mut(&self.key).add(cookie),
+            CookieProtection::Encrypted => jar.private_mut(&self.key).add(cookie),
+        }
+        jar.get(&self.name).unwrap().value().to_string()
+    }
+
+    fn cookie(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build((self.name.clone(), value))
+            .path(self.path.clone())
+            .same_site(SameSite::Lax)
+            .secure(self.secure)
+            .http_only(self.http_only);
+
+        if let Some(domain) = &self.domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        if let Some(expiry) = self.expiry {
+            cookie = cookie.max_age(expiry);
+        }
+
+        cookie.build()
+    }
+}
+
+/// Context of the key derivation, so the credentials key differs from the cookie keys.
+const CREDENTIALS_KEY_CONTEXT: &str = "datastar-axum-todolist 2025 session credentials";
+
+/// Encrypts the credentials of signed in users, so access tokens aren't readable in the
+/// database or its backups. Credentials of a previous key are still decrypted.
+#[derive(Clone)]
+pub(crate) struct CredentialsCipher {
+    /// The current key first.
+    keys: Vec<[u8; 32]>,
+}
+
+impl CredentialsCipher {
+    /// The random nonce followed by the ciphertext.
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let cipher = XChaCha20Poly1305::new(&self.keys[0].into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .expect("Encryption only fails for huge plaintexts");
+
+        [nonce.as_slice(), &ciphertext].concat()
+    }
+
+    /// Decrypts with the first key that fits, `None` if no key does.
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        let nonce_size = size_of::<XNonce>();
+        if sealed.len() < nonce_size {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(nonce_size);
+
+        self.keys.iter().find_map(|key| {
+            XChaCha20Poly1305::new(key.into())
+                .decrypt(XNonce::from_slice(nonce), ciphertext)
+                .ok()
+        })
+    }
+}
+
+impl std::fmt::Debug for CredentialsCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CredentialsCipher")
+            .field("keys", &self.keys.len())
+            .finish()
+    }
+}
+
+fn parse_key(secret: &str) -> Key {
+    // The length is validated with the configuration
+    Key::try_from(secret.as_bytes()).expect("Session secrets are at least 64 bytes long")
+}
+
+/// Replaces a session cookie of a previous key with one of the current key, so sessions survive
+/// a key rotation. It must run before the session layer.
+pub(crate) async fn rotate_keys(
+    State(config): State<Arc<SessionCookieConfig>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(cookie) = request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+        .find(|cookie| cookie.name() == config.name)
+        .map(Cookie::into_owned)
+    else {
+        return next.run(request).await;
+    };
+
+    if config.previous_keys.is_empty() || config.open(&config.key, cookie.clone()).is_some() {
+        return next.run(request).await;
+    }
+
+    let Some(session_id) = config
+        .previous_keys
+        .iter()
+        .find_map(|key| config.open(key, cookie.clone()))
+    else {
+        return next.run(request).await;
+    };
+
+    debug!("Replacing a session cookie of a previous key");
+    let sealed = config.seal(session_id);
+
+    // The session layer only sees the cookie of the current key
+    let cookies = request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+        .map(|other| {
+            if other.name() == config.name {
+                format!("{}={}", config.name, sealed)
+            } else {
+                other.stripped().to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    request.headers_mut().remove(COOKIE);
+    if let Ok(value) = HeaderValue::from_str(&cookies) {
+        request.headers_mut().insert(COOKIE, value);
+    }
+
+    let mut response = next.run(request).await;
+
+    // Unless the session layer set the cookie, e.g. after a sign-out
+    let cookie_prefix = format!("{}=", config.name);
+    let already_set = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .any(|header| header.starts_with(&cookie_prefix));
+    if !already_set {
+        let cookie = config.cookie(sealed).to_string();
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body::Body, http::HeaderMap, middleware, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const OLD_SECRET: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    const NEW_SECRET: &str = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+
+    fn cookie_config(secret: &str, previous_secrets: &[&str]) -> SessionCookieConfig {
+        let config = SessionConfig {
+            secret: Some(secret.to_string()),
+            previous_secrets: previous_secrets.iter().map(|s| s.to_string()).collect(),
+            ..SessionConfig::default()
+        };
+        SessionCookieConfig::new(&config, false)
+    }
+
+    #[test]
+    fn credentials_of_the_previous_key_are_decrypted_after_a_rotation() {
+        let sealed = cookie_config(OLD_SECRET, &[])
+            .credentials_cipher()
+            .seal(b"token");
+
+        let rotated = cookie_config(NEW_SECRET, &[OLD_SECRET]).credentials_cipher();
+        assert_eq!(rotated.open(&sealed).as_deref(), Some(&b"token"[..]));
+
+        // New credentials are encrypted with the new key
+        let resealed = rotated.seal(b"token");
+        assert!(
+            cookie_config(OLD_SECRET, &[])
+                .credentials_cipher()
+                .open(&resealed)
+                .is_none()
+        );
+        assert!(
+            cookie_config(NEW_SECRET, &[])
+                .credentials_cipher()
+                .open(&sealed)
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn replaces_cookies_of_the_previous_key() {
+        let old = cookie_config(OLD_SECRET, &[]);
+        let cookie = format!("{}={}", old.name, old.seal("session-id".to_string()));
+
+        let config = Arc::new(cookie_config(NEW_SECRET, &[OLD_SECRET]));
+        let app = Router::new()
+            .route(
+                "/",
+                get(|headers: HeaderMap| async move {
+                    headers
+                        .get(COOKIE)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string()
+                }),
+            )
+            .layer(middleware::from_fn_with_state(config.clone(), rotate_keys));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let set_cookie = response
+            .headers()
+            .get(SET_COOKIE)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let replaced = Cookie::parse(set_cookie.to_string()).unwrap();
+        assert_eq!(
+            config.open(&config.key, replaced.into_owned()).as_deref(),
+            Some("session-id")
+        );
+    }
+}
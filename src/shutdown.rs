@@ -0,0 +1,178 @@
+use std::{sync::Arc, time::Duration};
+
+use async_stream::stream;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use datastar::Sse;
+use http_body_util::BodyExt;
+use tokio::{signal, sync::watch};
+use tracing::{error, info, warn};
+
+use crate::fragments::ReconnectFragment;
+
+/// Clients reload after this long, so the load balancer has noticed that this instance is gone.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    Running,
+    /// No new connections are accepted, in-flight requests finish.
+    Draining,
+    /// The deadline passed, open SSE streams are told to reconnect and closed.
+    Closing,
+}
+
+/// Shutdown of the server on SIGTERM or SIGINT.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    phase: Arc<watch::Sender<Phase>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            phase: Arc::new(watch::Sender::new(Phase::Running)),
+        }
+    }
+
+    /// Waits for the signal, then gives in-flight requests `timeout` to finish.
+    pub async fn listen(self, timeout: Duration) {
+        wait_for_signal().await;
+        info!(
+            "Shutting down, in-flight requests have {}s to finish",
+            timeout.as_secs()
+        );
+        self.phase.send_replace(Phase::Draining);
+
+        tokio::time::sleep(timeout).await;
+        warn!("Shutdown deadline passed, closing the remaining connections");
+        self.phase.send_replace(Phase::Closing);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.phase.borrow() >= Phase::Draining
+    }
+
+    /// Resolves once the shutdown started.
+    pub fn draining(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.reached(Phase::Draining)
+    }
+
+    /// Resolves once the deadline of the shutdown passed.
+    pub fn closing(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.reached(Phase::Closing)
+    }
+
+    fn reached(&self, phase: Phase) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.phase.subscribe();
+        async move {
+            // The sender lives as long as any shutdown handle, so this doesn't fail
+            let _ = receiver.wait_for(|current| *current >= phase).await;
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(err) = signal::ctrl_c().await {
+            error!("Failed to listen for SIGINT: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                error!("Failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Ends SSE streams that are still open at the shutdown deadline with an event that makes the
+/// page reconnect. Streams are only ended between two events, never within one.
+pub(crate) async fn drain_streams(
+    State(shutdown): State<Shutdown>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+
+    let is_stream = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"));
+    if !is_stream {
+        return response;
+    }
+
+    response.map(|mut body| {
+        Body::from_stream(stream! {
+            let mut closing = std::pin::pin!(shutdown.closing());
+
+            loop {
+                let frame = tokio::select! {
+                    frame = body.frame() => Some(frame),
+                    _ = &mut closing => None,
+                };
+
+                match frame {
+                    Some(Some(Ok(frame))) => {
+                        if let Ok(data) = frame.into_data() {
+                            yield Ok(data);
+                        }
+                    }
+                    Some(Some(Err(err))) => {
+                        yield Err(err);
+                        break;
+                    }
+                    Some(None) => break,
+                    None => {
+                        let mut reconnect = reconnect().into_body();
+                        while let Some(Ok(frame)) = reconnect.frame().await {
+                            if let Ok(data) = frame.into_data() {
+                                yield Ok(data);
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+        })
+    })
+}
+
+fn reconnect() -> Response {
+    let fragment = ReconnectFragment {
+        delay: RECONNECT_DELAY,
+    };
+
+    Sse(stream! {
+        yield fragment.fragment().unwrap().into();
+    })
+    .into_response()
+}
@@ -1,18 +1,125 @@
+use std::sync::Arc;
+
 use axum::extract::FromRef;
 use sqlx::{Pool, Sqlite};
 
-use crate::{repository::NoteRepository, service::NoteService};
+use crate::{
+    config::Config,
+    db::note_storage,
+    metrics::Metrics,
+    repository::{
+        AccessTokenRepository, AuditLogRepository, CspReportRepository, MaintenanceRepository,
+        SessionRepository, UserRepository,
+    },
+    service::{
+        AccessTokenService, AuditLogService, BackupService, CspReportService, HealthService,
+        LocalAuthBackend, NoteService, OidcAuthBackend, SessionService,
+    },
+    session_cookie::SessionCookieConfig,
+    shutdown::Shutdown,
+};
 
-#[derive(Debug, Clone, FromRef)]
+#[derive(Clone, FromRef)]
 pub struct AppState {
+    config: Arc<Config>,
     notes: NoteService,
+    tokens: AccessTokenService,
+    auth: OidcAuthBackend,
+    sessions: SessionService,
+    session_store: SessionRepository,
+    session_cookie: Arc<SessionCookieConfig>,
+    csp_reports: CspReportService,
+    audit: AuditLogService,
+    health: HealthService,
+    metrics: Metrics,
+    shutdown: Shutdown,
+    backups: Option<BackupService>,
 }
 
 impl AppState {
-    pub async fn from_database(db: Pool<Sqlite>) -> Self {
-        let note_repository = NoteRepository::new(db.clone());
-        let notes = NoteService::new(note_repository);
+    pub async fn new(config: Arc<Config>, db: Pool<Sqlite>) -> Self {
+        let notes = NoteService::new(note_storage(&config.database, db.clone()).await);
+
+        let token_repository = AccessTokenRepository::new(db.clone());
+        let tokens = AccessTokenService::new(token_repository);
+
+        let session_cookie = Arc::new(SessionCookieConfig::new(
+            &config.session,
+            config.server.is_https(),
+        ));
+        let session_store = SessionRepository::new(db.clone(), session_cookie.credentials_cipher());
+        let sessions = SessionService::new(session_store.clone());
+
+        let csp_reports = CspReportService::new(CspReportRepository::new(db.clone()));
+        let audit = AuditLogService::new(AuditLogRepository::new(db.clone()));
+
+        let user_repository = UserRepository::new(db.clone());
+        let local = config
+            .local_auth
+            .enabled
+            .then(|| LocalAuthBackend::new(config.local_auth.clone(), user_repository.clone()));
+
+        let auth = OidcAuthBackend::new(
+            &config,
+            local,
+            user_repository,
+            session_store.clone(),
+            audit.clone(),
+        )
+        .await
+        .expect("Failed to create OIDC backend");
+
+        let shutdown = Shutdown::new();
+        let health = HealthService::new(
+            MaintenanceRepository::new(db.clone()),
+            auth.clone(),
+            shutdown.clone(),
+        );
+        let backups = config
+            .database
+            .backup
+            .clone()
+            .map(|backup| BackupService::new(MaintenanceRepository::new(db.clone()), backup));
+        let metrics = Metrics::install(config.metrics.clone(), db, auth.clone());
+
+        Self {
+            config,
+            notes,
+            tokens,
+            auth,
+            sessions,
+            session_store,
+            session_cookie,
+            csp_reports,
+            audit,
+            health,
+            metrics,
+            shutdown,
+            backups,
+        }
+    }
+
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub(crate) fn auth(&self) -> &OidcAuthBackend {
+        &self.auth
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    pub(crate) fn session_store(&self) -> &SessionRepository {
+        &self.session_store
+    }
+
+    pub(crate) fn session_cookie(&self) -> &Arc<SessionCookieConfig> {
+        &self.session_cookie
+    }
 
-        Self { notes }
+    pub(crate) fn backups(&self) -> Option<&BackupService> {
+        self.backups.as_ref()
     }
 }
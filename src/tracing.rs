@@ -0,0 +1,197 @@
+use axum::{extract::Request, http::HeaderMap};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use serde::{Deserialize, Serialize};
+use tracing::{Span, info_span, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{filter::EnvFilter, fmt};
+
+use crate::config::Config;
+
+/// Header of the request id, it is generated unless the client or a proxy sent one.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown log format {value}, expected text or json")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TracingConfig {
+    pub log_format: LogFormat,
+    /// OTLP/HTTP endpoint the spans are exported to, e.g. `http://localhost:4318/v1/traces`.
+    /// Nothing is exported without it.
+    pub otlp_endpoint: Option<String>,
+    /// Service name of the exported spans.
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            log_format: LogFormat::Text,
+            otlp_endpoint: None,
+            service_name: env!("CARGO_PKG_NAME").to_string(),
+        }
+    }
+}
+
+/// Exports the remaining spans when it is dropped, keep it until the process exits.
+pub struct TracingGuard {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if let Some(Err(err)) = self.provider.take().map(|provider| provider.shutdown()) {
+            eprintln!("Failed to export the remaining spans: {err}");
+        }
+    }
+}
+
+pub fn init_tracing(config: &Config) -> TracingGuard {
+    let config = &config.tracing;
+
+    let filter_layer = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new("info"))
+        .unwrap();
+
+    let (text_layer, json_layer) = match config.log_format {
+        LogFormat::Text => (
+            Some(
+                fmt::layer()
+                    .with_span_events(FmtSpan::CLOSE)
+                    .with_target(true),
+            ),
+            None,
+        ),
+        // Fields of the enclosing spans, like the request id, are part of every line
+        LogFormat::Json => (
+            None,
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true)
+                    .with_target(true),
+            ),
+        ),
+    };
+
+    // Incoming requests continue the trace of their caller
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let (provider, exporter_error) = match &config.otlp_endpoint {
+        Some(endpoint) => match tracer_provider(endpoint, &config.service_name) {
+            Ok(provider) => (Some(provider), None),
+            Err(err) => (None, Some(err)),
+        },
+        None => (None, None),
+    };
+    let otel_layer = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(text_layer)
+        .with(json_layer)
+        .with(otel_layer)
+        .init();
+
+    if let Some(err) = exporter_error {
+        warn!(
+            "Failed to create the OTLP exporter, spans are not exported: {}",
+            err
+        );
+    }
+
+    TracingGuard { provider }
+}
+
+fn tracer_provider(
+    endpoint: &str,
+    service_name: &str,
+) -> Result<SdkTracerProvider, ExporterBuildError> {
+    // The exporter uses a blocking HTTP client, which must not be created on a runtime thread
+    let endpoint = endpoint.to_string();
+    let exporter = std::thread::spawn(move || {
+        SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+    })
+    .join()
+    .expect("Failed to create the OTLP exporter")?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build();
+    global::set_tracer_provider(provider.clone());
+
+    Ok(provider)
+}
+
+/// Span of a request, with the request id and the trace context of the caller.
+pub fn request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let span = info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id,
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent);
+
+    span
+}
+
+/// Reads the `traceparent` and `tracestate` headers.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
@@ -0,0 +1,61 @@
+use askama::Template;
+use axum::response::Html;
+
+use crate::{
+    auth::AuthSession,
+    csrf::CsrfToken,
+    headers::CspNonce,
+    model::{Role, UserIdentity},
+    view::login::ProviderLink,
+};
+
+#[derive(Template)]
+#[template(path = "account.html")]
+pub(crate) struct Account {
+    title: String,
+    partial: bool,
+    csrf_token: String,
+    csp_nonce: String,
+    identities: Vec<UserIdentity>,
+    providers: Vec<ProviderLink>,
+    has_password: bool,
+    is_admin: bool,
+}
+
+pub(crate) async fn account(
+    auth_session: AuthSession,
+    CsrfToken(csrf_token): CsrfToken,
+    CspNonce(csp_nonce): CspNonce,
+) -> Html<String> {
+    let user = auth_session.user.unwrap();
+    let identities = auth_session.backend.get_identities(user.id).await.unwrap();
+    let account = auth_session.backend.get_account(user.id).await.unwrap();
+
+    // Only offer providers the user hasn't linked an identity of yet
+    let providers = auth_session
+        .backend
+        .providers()
+        .iter()
+        .filter(|provider| {
+            !identities
+                .iter()
+                .any(|identity| identity.provider == provider.id)
+        })
+        .map(ProviderLink::from)
+        .collect();
+
+    Html(
+        Account {
+            title: "Account".to_owned(),
+            partial: false,
+            csrf_token,
+            csp_nonce,
+            identities,
+            providers,
+            has_password: account.is_some_and(|account| account.password.is_some()),
+            is_admin: user.roles.contains(&Role::Admin),
+        }
+        .render()
+        .unwrap(),
+    )
+}
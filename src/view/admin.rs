@@ -0,0 +1,228 @@
+use askama::Template;
+use async_stream::stream;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use datastar::{Sse, prelude::RemoveFragments};
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthSession, ClientInfo},
+    csrf::CsrfToken,
+    fragments::{AdminUserFragment, CSP_REPORTS_SELECTOR},
+    headers::CspNonce,
+    model::{AuditEvent, AuditRecord, CspReport, UserId},
+    service::{AuditLogService, CspReportService, NoteService},
+    view::{action_error, page_error},
+};
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+pub(crate) struct Admin {
+    title: String,
+    partial: bool,
+    csrf_token: String,
+    csp_nonce: String,
+    users: Vec<AdminUserFragment>,
+    csp_reports: Vec<CspReport>,
+}
+
+pub(crate) async fn admin(
+    State(csp_reports): State<CspReportService>,
+    State(notes): State<NoteService>,
+    auth_session: AuthSession,
+    CsrfToken(csrf_token): CsrfToken,
+    CspNonce(csp_nonce): CspNonce,
+) -> Response {
+    let admin = auth_session.user.as_ref().unwrap();
+
+    let overviews = match auth_session.backend.get_user_overviews().await {
+        Ok(overviews) => overviews,
+        Err(error) => return page_error("Failed to get the users", error),
+    };
+    let note_counts = match notes.count_notes().await {
+        Ok(note_counts) => note_counts,
+        Err(error) => return page_error("Failed to count the notes", error),
+    };
+    let csp_reports = match csp_reports.get_reports().await {
+        Ok(csp_reports) => csp_reports,
+        Err(error) => return page_error("Failed to get the CSP reports", error),
+    };
+
+    let users = overviews
+        .into_iter()
+        .map(|mut user| {
+            user.note_count = note_counts.get(&user.id).copied().unwrap_or(0);
+            user
+        })
+        .map(|user| AdminUserFragment {
+            signed_in: auth_session.backend.is_signed_in(user.id),
+            current: user.id == admin.id,
+            user,
+        })
+        .collect();
+
+    Html(
+        Admin {
+            title: "Administration".to_owned(),
+            partial: false,
+            csrf_token,
+            csp_nonce,
+            users,
+            csp_reports,
+        }
+        .render()
+        .unwrap(),
+    )
+    .into_response()
+}
+
+pub(crate) async fn force_logout(
+    Path(id): Path<Uuid>,
+    State(audit): State<AuditLogService>,
+    State(notes): State<NoteService>,
+    auth_session: AuthSession,
+    client: ClientInfo,
+) -> impl IntoResponse {
+    let user_id = UserId(id);
+    if is_current(&auth_session, user_id) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    if let Err(error) = auth_session.backend.force_logout(user_id).await {
+        return action_error("Failed to sign out the user", error);
+    }
+    audit
+        .record(
+            admin_record(&auth_session, &client, AuditEvent::Logout, user_id)
+                .details("signed out everywhere by an administrator"),
+        )
+        .await;
+
+    user_row(&auth_session, &notes, user_id).await
+}
+
+pub(crate) async fn disable_user(
+    Path(id): Path<Uuid>,
+    State(audit): State<AuditLogService>,
+    State(notes): State<NoteService>,
+    auth_session: AuthSession,
+    client: ClientInfo,
+) -> impl IntoResponse {
+    set_disabled(audit, notes, auth_session, client, UserId(id), true).await
+}
+
+pub(crate) async fn enable_user(
+    Path(id): Path<Uuid>,
+    State(audit): State<AuditLogService>,
+    State(notes): State<NoteService>,
+    auth_session: AuthSession,
+    client: ClientInfo,
+) -> impl IntoResponse {
+    set_disabled(audit, notes, auth_session, client, UserId(id), false).await
+}
+
+pub(crate) async fn clear_csp_reports(
+    State(csp_reports): State<CspReportService>,
+    State(audit): State<AuditLogService>,
+    auth_session: AuthSession,
+    client: ClientInfo,
+) -> impl IntoResponse {
+    let cleared = match csp_reports.clear_reports().await {
+        Ok(cleared) => cleared,
+        Err(error) => return action_error("Failed to clear the CSP reports", error),
+    };
+
+    let mut record = client
+        .audit(AuditEvent::BulkDeletion)
+        .details(format!("cleared {cleared} CSP reports"));
+    if let Some(admin) = &auth_session.user {
+        record = record.actor(admin.id);
+    }
+    audit.record(record).await;
+
+    Sse(stream! {
+        yield RemoveFragments::new(CSP_REPORTS_SELECTOR).into();
+    })
+    .into_response()
+}
+
+async fn set_disabled(
+    audit: AuditLogService,
+    notes: NoteService,
+    auth_session: AuthSession,
+    client: ClientInfo,
+    user_id: UserId,
+    disabled: bool,
+) -> Response {
+    // Administrators can't lock themselves out
+    if is_current(&auth_session, user_id) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    if let Err(error) = auth_session.backend.set_disabled(user_id, disabled).await {
+        return action_error("Failed to change the user", error);
+    }
+
+    let event = if disabled {
+        AuditEvent::UserDisabled
+    } else {
+        AuditEvent::UserEnabled
+    };
+    audit
+        .record(admin_record(&auth_session, &client, event, user_id))
+        .await;
+
+    user_row(&auth_session, &notes, user_id).await
+}
+
+/// An event the signed in administrator caused for another user.
+fn admin_record(
+    auth_session: &AuthSession,
+    client: &ClientInfo,
+    event: AuditEvent,
+    user_id: UserId,
+) -> AuditRecord {
+    let record = client.audit(event).user(user_id);
+
+    match &auth_session.user {
+        Some(admin) => record.actor(admin.id),
+        None => record,
+    }
+}
+
+fn is_current(auth_session: &AuthSession, user_id: UserId) -> bool {
+    auth_session
+        .user
+        .as_ref()
+        .is_some_and(|user| user.id == user_id)
+}
+
+/// Re-renders the row of the user after it changed.
+async fn user_row(auth_session: &AuthSession, notes: &NoteService, user_id: UserId) -> Response {
+    let mut user = match auth_session.backend.get_user_overview(user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(error) => return action_error("Failed to get the user", error),
+    };
+    user.note_count = match notes.count_notes().await {
+        Ok(note_counts) => note_counts.get(&user_id).copied().unwrap_or(0),
+        Err(error) => return action_error("Failed to count the notes", error),
+    };
+
+    let row = AdminUserFragment {
+        signed_in: auth_session.backend.is_signed_in(user_id),
+        current: false,
+        user,
+    };
+
+    Sse(stream! {
+        yield row
+            .fragment()
+            .unwrap()
+            .into();
+    })
+    .into_response()
+}
@@ -0,0 +1,140 @@
+use askama::Template;
+use axum::{
+    extract::{Query, RawQuery, State},
+    http::{
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{NaiveDate, TimeDelta};
+use serde::Deserialize;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    csrf::CsrfToken,
+    headers::CspNonce,
+    model::{AuditEntry, AuditEvent, AuditFilter, UserId},
+    service::AuditLogService,
+};
+
+/// Filter of the audit log as sent by the filter form, empty fields match all entries.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct AuditQuery {
+    #[serde(default)]
+    event: String,
+    #[serde(default)]
+    user: String,
+    /// First day, `YYYY-MM-DD` in UTC.
+    #[serde(default)]
+    since: String,
+    /// Last day, `YYYY-MM-DD` in UTC.
+    #[serde(default)]
+    until: String,
+}
+
+impl AuditQuery {
+    fn filter(&self) -> Result<AuditFilter, String> {
+        let day = |value: &str| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+                .map_err(|_| format!("invalid date {value}"))
+        };
+
+        Ok(AuditFilter {
+            event: Some(self.event.as_str())
+                .filter(|event| !event.is_empty())
+                .map(str::parse)
+                .transpose()?,
+            user_id: Some(self.user.trim())
+                .filter(|user| !user.is_empty())
+                .map(|user| Uuid::parse_str(user).map(UserId))
+                .transpose()
+                .map_err(|_| format!("invalid user id {}", self.user))?,
+            since: Some(self.since.as_str())
+                .filter(|since| !since.is_empty())
+                .map(day)
+                .transpose()?,
+            // The last day is included
+            until: Some(self.until.as_str())
+                .filter(|until| !until.is_empty())
+                .map(|until| day(until).map(|until| until + TimeDelta::days(1)))
+                .transpose()?,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "audit.html")]
+pub(crate) struct Audit {
+    title: String,
+    partial: bool,
+    csrf_token: String,
+    csp_nonce: String,
+    events: [AuditEvent; 11],
+    query: AuditQuery,
+    /// Query string of the filter, passed on to the export.
+    export_query: String,
+    entries: Vec<AuditEntry>,
+}
+
+pub(crate) async fn audit(
+    State(audit): State<AuditLogService>,
+    Query(query): Query<AuditQuery>,
+    RawQuery(raw_query): RawQuery,
+    CsrfToken(csrf_token): CsrfToken,
+    CspNonce(csp_nonce): CspNonce,
+) -> Response {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(error) => return (StatusCode::BAD_REQUEST, error).into_response(),
+    };
+
+    Html(
+        Audit {
+            title: "Audit log".to_owned(),
+            partial: false,
+            csrf_token,
+            csp_nonce,
+            events: AuditEvent::ALL,
+            query,
+            export_query: raw_query.unwrap_or_default(),
+            entries: audit.get_entries(&filter).await.unwrap(),
+        }
+        .render()
+        .unwrap(),
+    )
+    .into_response()
+}
+
+/// Exports the entries matching the filter as JSON lines, e.g. for a SIEM.
+pub(crate) async fn export(
+    State(audit): State<AuditLogService>,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(error) => return (StatusCode::BAD_REQUEST, error).into_response(),
+    };
+
+    let lines = match audit.export(&filter).await {
+        Ok(lines) => lines,
+        Err(error) => {
+            error!("Failed to export the audit log: {}", error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    (
+        [
+            (CONTENT_TYPE, "application/x-ndjson"),
+            (
+                CONTENT_DISPOSITION,
+                r#"attachment; filename="audit-log.jsonl""#,
+            ),
+        ],
+        lines,
+    )
+        .into_response()
+}
@@ -0,0 +1,139 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+};
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use crate::{auth::ClientInfo, model::CspViolation, service::CspReportService};
+
+/// Report sent to `report-uri` with content type `application/csp-report`.
+#[derive(Deserialize)]
+struct LegacyReport {
+    #[serde(rename = "csp-report")]
+    report: LegacyViolation,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LegacyViolation {
+    #[serde(default)]
+    document_uri: String,
+    #[serde(default)]
+    violated_directive: String,
+    effective_directive: Option<String>,
+    #[serde(default)]
+    blocked_uri: String,
+    #[serde(default)]
+    source_file: String,
+    #[serde(default)]
+    line_number: i64,
+    disposition: Option<String>,
+    script_sample: Option<String>,
+}
+
+impl From<LegacyViolation> for CspViolation {
+    fn from(report: LegacyViolation) -> Self {
+        Self {
+            document_uri: report.document_uri,
+            directive: report
+                .effective_directive
+                .unwrap_or(report.violated_directive),
+            blocked_uri: report.blocked_uri,
+            source_file: report.source_file,
+            line_number: report.line_number,
+            disposition: report.disposition.unwrap_or("enforce".to_string()),
+            sample: report.script_sample.filter(|sample| !sample.is_empty()),
+        }
+    }
+}
+
+/// Report sent to `report-to` with content type `application/reports+json`, it may also
+/// contain other types than CSP violations.
+#[derive(Deserialize)]
+struct ReportingApiReport {
+    #[serde(rename = "type")]
+    kind: String,
+    body: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportingApiViolation {
+    #[serde(rename = "documentURL", default)]
+    document_url: String,
+    #[serde(default)]
+    effective_directive: String,
+    #[serde(rename = "blockedURL", default)]
+    blocked_url: String,
+    #[serde(default)]
+    source_file: String,
+    #[serde(default)]
+    line_number: i64,
+    disposition: Option<String>,
+    sample: Option<String>,
+}
+
+impl From<ReportingApiViolation> for CspViolation {
+    fn from(report: ReportingApiViolation) -> Self {
+        Self {
+            document_uri: report.document_url,
+            directive: report.effective_directive,
+            blocked_uri: report.blocked_url,
+            source_file: report.source_file,
+            line_number: report.line_number,
+            disposition: report.disposition.unwrap_or("enforce".to_string()),
+            sample: report.sample.filter(|sample| !sample.is_empty()),
+        }
+    }
+}
+
+/// Receives the violation reports of the Content Security Policy, in the legacy `report-uri`
+/// format as well as in the format of the Reporting API.
+pub(crate) async fn csp_report(
+    State(reports): State<CspReportService>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let violations = if content_type.starts_with("application/reports+json") {
+        serde_json::from_slice::<Vec<ReportingApiReport>>(&body).map(|reports| {
+            reports
+                .into_iter()
+                .filter(|report| report.kind == "csp-violation")
+                .filter_map(|report| {
+                    serde_json::from_value::<ReportingApiViolation>(report.body).ok()
+                })
+                .map(CspViolation::from)
+                .collect::<Vec<_>>()
+        })
+    } else {
+        // Some browsers send the legacy format as `application/json`
+        serde_json::from_slice::<LegacyReport>(&body).map(|report| vec![report.report.into()])
+    };
+
+    let violations = match violations {
+        Ok(violations) => violations,
+        Err(err) => {
+            debug!("Invalid CSP report: {}", err);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    match reports
+        .report(client.ip_addr(), client.user_agent.as_deref(), violations)
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(error) => {
+            error!("Failed to store CSP report: {}", error);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
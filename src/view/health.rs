@@ -0,0 +1,21 @@
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde_json::json;
+
+use crate::service::{CheckStatus, HealthService};
+
+/// Liveness probe, answers as long as the process serves requests.
+pub(crate) async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness probe, fails with 503 while a dependency is unavailable. Probes aren't
+/// authenticated, so the failed checks are only logged.
+pub(crate) async fn readyz(State(health): State<HealthService>) -> impl IntoResponse {
+    let readiness = health.readiness().await;
+
+    let status = match readiness {
+        CheckStatus::Ok => StatusCode::OK,
+        CheckStatus::Fail => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(json!({ "status": readiness })))
+}
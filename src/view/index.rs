@@ -1,24 +1,38 @@
 use askama::Template;
 use axum::{extract::State, response::Html};
+use tower_sessions::Session;
 
-use crate::{auth::AuthSession, model, service::{NoteService}};
+use crate::{
+    auth::CurrentUser, csrf::CsrfToken, fragments::ToastFragment, headers::CspNonce, model, replay,
+    service::NoteService,
+};
 
 #[derive(Template)]
 #[template(path = "index.html")]
 pub(crate) struct Index {
     title: String,
     partial: bool,
+    csrf_token: String,
+    csp_nonce: String,
     notes: Vec<model::Note>,
+    toast: Option<ToastFragment>,
 }
 
-pub(crate) async fn index(State(notes): State<NoteService>, auth_session: AuthSession) -> Html<String> {
-    let user = auth_session.user.unwrap();
-
+pub(crate) async fn index(
+    State(notes): State<NoteService>,
+    user: CurrentUser,
+    session: Session,
+    CsrfToken(csrf_token): CsrfToken,
+    CspNonce(csp_nonce): CspNonce,
+) -> Html<String> {
     Html(
         Index {
             title: "TodoList".to_owned(),
             partial: false,
+            csrf_token,
+            csp_nonce,
             notes: notes.get_notes(user.id).await.unwrap(),
+            toast: replay::take_toast(&session).await,
         }
         .render()
         .unwrap(),
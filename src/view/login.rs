@@ -0,0 +1,95 @@
+use askama::Template;
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::{
+    auth::AuthSession,
+    csrf::CsrfToken,
+    headers::CspNonce,
+    service::{LocalAuthBackend, OidcProvider},
+};
+
+/// Name and id of a provider as shown on the chooser.
+pub(crate) struct ProviderLink {
+    pub id: String,
+    pub name: String,
+}
+
+impl From<&OidcProvider> for ProviderLink {
+    fn from(provider: &OidcProvider) -> Self {
+        Self {
+            id: provider.id.clone(),
+            name: provider.name.clone(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub(crate) struct ProviderChooser {
+    title: String,
+    partial: bool,
+    csrf_token: String,
+    csp_nonce: String,
+    providers: Vec<ProviderLink>,
+    local_login: bool,
+    signup: bool,
+}
+
+pub(crate) fn provider_chooser(
+    providers: &[OidcProvider],
+    local: Option<&LocalAuthBackend>,
+    csrf_token: String,
+    csp_nonce: String,
+) -> Html<String> {
+    Html(
+        ProviderChooser {
+            title: "Sign in".to_owned(),
+            partial: false,
+            csrf_token,
+            csp_nonce,
+            providers: providers.iter().map(ProviderLink::from).collect(),
+            local_login: local.is_some(),
+            signup: local.is_some_and(LocalAuthBackend::signup_enabled),
+        }
+        .render()
+        .unwrap(),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "signup.html")]
+pub(crate) struct Signup {
+    title: String,
+    partial: bool,
+    csrf_token: String,
+    csp_nonce: String,
+}
+
+pub(crate) async fn signup(
+    auth_session: AuthSession,
+    CsrfToken(csrf_token): CsrfToken,
+    CspNonce(csp_nonce): CspNonce,
+) -> Response {
+    if !auth_session
+        .backend
+        .local()
+        .is_some_and(LocalAuthBackend::signup_enabled)
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    Html(
+        Signup {
+            title: "Sign up".to_owned(),
+            partial: false,
+            csrf_token,
+            csp_nonce,
+        }
+        .render()
+        .unwrap(),
+    )
+    .into_response()
+}
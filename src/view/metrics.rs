@@ -0,0 +1,26 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::{auth::ClientInfo, metrics::Metrics};
+
+/// Metrics in the Prometheus text format, for clients with the token or an allowed address.
+pub(crate) async fn metrics(
+    State(metrics): State<Metrics>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Response {
+    if !metrics.allows(client.ip.as_deref(), &headers) {
+        warn!("Denied access to the metrics from {:?}", client.ip);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+        .into_response()
+}
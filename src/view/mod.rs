@@ -1,2 +1,40 @@
+use std::fmt::Display;
+
+use async_stream::stream;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use datastar::Sse;
+use tracing::error;
+
+use crate::fragments::ToastFragment;
+
+pub mod account;
+pub mod admin;
+pub mod audit;
+pub mod csp;
+pub mod health;
 pub mod index;
+pub mod login;
+pub mod metrics;
 pub mod note;
+pub mod session;
+pub mod token;
+
+/// The page can't be shown without the data, the error is only logged.
+pub(crate) fn page_error(message: &str, error: impl Display) -> Response {
+    error!("{}: {}", message, error);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+/// Tells the user about a failed action with a toast, the error is only logged.
+pub(crate) fn action_error(message: &str, error: impl Display) -> Response {
+    error!("{}: {}", message, error);
+    let toast = ToastFragment::error("That didn't work, please try again.");
+
+    Sse(stream! {
+        yield toast.fragment().unwrap().into();
+    })
+    .into_response()
+}
@@ -13,7 +13,7 @@ use serde::Deserialize;
 use uuid::Uuid;
 
 use crate::{
-    auth::AuthSession,
+    auth::CurrentUser,
     fragments::{EditNoteFragment, NOTE_LIST_ID, NoteFragment, note_selector},
     service::NoteService,
 };
@@ -21,12 +21,8 @@ use crate::{
 pub(crate) async fn delete_note(
     Path(id): Path<Uuid>,
     State(notes): State<NoteService>,
-    auth_session: AuthSession,
+    user: CurrentUser,
 ) -> impl IntoResponse {
-    let user = auth_session
-        .user
-        .expect("User must be logged in to use this endpoint");
-
     notes.delete_note(user.id, id).await.unwrap();
 
     Sse(stream! {
@@ -37,12 +33,8 @@ pub(crate) async fn delete_note(
 pub(crate) async fn edit_note_view(
     Path(id): Path<Uuid>,
     State(notes): State<NoteService>,
-    auth_session: AuthSession,
+    user: CurrentUser,
 ) -> impl IntoResponse {
-    let user = auth_session
-        .user
-        .expect("User must be logged in to use this endpoint");
-
     let note = EditNoteFragment {
         note: notes.get_note(user.id, id).await.unwrap(),
     };
@@ -58,12 +50,8 @@ pub(crate) async fn edit_note_view(
 pub(crate) async fn get_note(
     Path(id): Path<Uuid>,
     State(notes): State<NoteService>,
-    auth_session: AuthSession,
+    user: CurrentUser,
 ) -> impl IntoResponse {
-    let user = auth_session
-        .user
-        .expect("User must be logged in to use this endpoint");
-
     let note = NoteFragment {
         note: notes.get_note(user.id, id).await.unwrap(),
     };
@@ -84,19 +72,15 @@ pub(crate) struct UpdateSignals {
 pub(crate) async fn update_note(
     Path(id): Path<Uuid>,
     State(notes): State<NoteService>,
-    auth_session: AuthSession,
+    user: CurrentUser,
     ReadSignals(signals): ReadSignals<UpdateSignals>,
 ) -> impl IntoResponse {
-    let user = auth_session
-        .user
-        .expect("User must be logged in to use this endpoint");
-
     let note = notes
         .update_note_content(user.id, id, &signals.content)
         .await
         .unwrap();
 
-    let note = NoteFragment { note: note };
+    let note = NoteFragment { note };
 
     Sse(stream! {
         yield note
@@ -113,16 +97,12 @@ pub(crate) struct NewNoteSignals {
 
 pub(crate) async fn new_note(
     State(notes): State<NoteService>,
-    auth_session: AuthSession,
+    user: CurrentUser,
     ReadSignals(signals): ReadSignals<NewNoteSignals>,
 ) -> impl IntoResponse {
-    let user = auth_session
-        .user
-        .expect("User must be logged in to use this endpoint");
-
     let note = notes.create_note(user.id, &signals.note).await.unwrap();
 
-    let note = NoteFragment { note: note };
+    let note = NoteFragment { note };
 
     Sse(stream! {
         // Clear the input field for the note
@@ -141,18 +121,11 @@ pub(crate) async fn new_note(
 pub(crate) async fn check_note(
     Path(id): Path<Uuid>,
     State(notes): State<NoteService>,
-    auth_session: AuthSession,
+    user: CurrentUser,
 ) -> impl IntoResponse {
-    let user = auth_session
-        .user
-        .expect("User must be logged in to use this endpoint");
+    let note = notes.update_note_checked(user.id, id, true).await.unwrap();
 
-    let note = notes
-        .update_note_checked(user.id, id, true)
-        .await
-        .unwrap();
-
-    let note = NoteFragment { note: note };
+    let note = NoteFragment { note };
 
     Sse(stream! {
         yield note
@@ -165,18 +138,11 @@ pub(crate) async fn check_note(
 pub(crate) async fn uncheck_note(
     Path(id): Path<Uuid>,
     State(notes): State<NoteService>,
-    auth_session: AuthSession,
+    user: CurrentUser,
 ) -> impl IntoResponse {
-    let user = auth_session
-        .user
-        .expect("User must be logged in to use this endpoint");
-
-    let note = notes
-        .update_note_checked(user.id, id, false)
-        .await
-        .unwrap();
+    let note = notes.update_note_checked(user.id, id, false).await.unwrap();
 
-    let note = NoteFragment { note: note };
+    let note = NoteFragment { note };
 
     Sse(stream! {
         yield note
@@ -0,0 +1,139 @@
+use askama::Template;
+use async_stream::stream;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use datastar::{Sse, prelude::RemoveFragments};
+use tower_sessions::Session;
+
+use crate::{
+    auth::{AuthSession, ClientInfo},
+    csrf::CsrfToken,
+    fragments::{OTHER_SESSIONS_SELECTOR, session_selector},
+    headers::CspNonce,
+    model::{ActiveSession, AuditEvent},
+    service::{AuditLogService, SessionService},
+    view::{action_error, page_error},
+};
+
+/// A session on the sessions page, the current one can't be revoked from there.
+pub(crate) struct SessionEntry {
+    pub session: ActiveSession,
+    pub current: bool,
+}
+
+#[derive(Template)]
+#[template(path = "sessions.html")]
+pub(crate) struct Sessions {
+    title: String,
+    partial: bool,
+    csrf_token: String,
+    csp_nonce: String,
+    sessions: Vec<SessionEntry>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+pub(crate) async fn sessions(
+    State(sessions): State<SessionService>,
+    auth_session: AuthSession,
+    session: Session,
+    CsrfToken(csrf_token): CsrfToken,
+    CspNonce(csp_nonce): CspNonce,
+) -> Response {
+    let user = auth_session.user.unwrap();
+    let current_id = session.id().map(|id| id.to_string());
+
+    let sessions = match sessions.get_sessions(user.id).await {
+        Ok(sessions) => sessions,
+        Err(error) => return page_error("Failed to get the sessions", error),
+    };
+    let expires_at = match auth_session.backend.get_session_expiry(user.key()).await {
+        Ok(expires_at) => expires_at,
+        Err(error) => return page_error("Failed to get the session expiry", error),
+    };
+
+    let sessions = sessions
+        .into_iter()
+        .map(|session| SessionEntry {
+            current: current_id.as_ref() == Some(&session.id),
+            session,
+        })
+        .collect();
+
+    Html(
+        Sessions {
+            title: "Active sessions".to_owned(),
+            partial: false,
+            csrf_token,
+            csp_nonce,
+            sessions,
+            expires_at,
+        }
+        .render()
+        .unwrap(),
+    )
+    .into_response()
+}
+
+pub(crate) async fn revoke_session(
+    Path(id): Path<String>,
+    State(sessions): State<SessionService>,
+    State(audit): State<AuditLogService>,
+    auth_session: AuthSession,
+    client: ClientInfo,
+) -> impl IntoResponse {
+    let user = auth_session
+        .user
+        .expect("User must be logged in to use this endpoint");
+
+    // The session id is the cookie value, it must not end up in the audit log
+    let revoked = match sessions.revoke_session(user.id, &id).await {
+        Ok(revoked) => revoked,
+        Err(error) => return action_error("Failed to revoke the session", error),
+    };
+    if revoked > 0 {
+        let record = client
+            .audit(AuditEvent::Logout)
+            .user(user.id)
+            .details("signed out another session");
+        audit.record(record).await;
+    }
+
+    Sse(stream! {
+        yield RemoveFragments::new(session_selector(&id)).into();
+    })
+    .into_response()
+}
+
+pub(crate) async fn revoke_other_sessions(
+    State(sessions): State<SessionService>,
+    State(audit): State<AuditLogService>,
+    auth_session: AuthSession,
+    session: Session,
+    client: ClientInfo,
+) -> impl IntoResponse {
+    let user = auth_session
+        .user
+        .expect("User must be logged in to use this endpoint");
+
+    // Without an id the current session isn't stored yet, so all stored ones are others
+    let current = session.id().map(|id| id.to_string()).unwrap_or_default();
+
+    let revoked = match sessions.revoke_other_sessions(user.id, &current).await {
+        Ok(revoked) => revoked,
+        Err(error) => return action_error("Failed to revoke the other sessions", error),
+    };
+
+    let record = client
+        .audit(AuditEvent::BulkDeletion)
+        .user(user.id)
+        .details(format!("signed out {revoked} other sessions"));
+    audit.record(record).await;
+
+    Sse(stream! {
+        yield RemoveFragments::new(OTHER_SESSIONS_SELECTOR).into();
+    })
+    .into_response()
+}
@@ -0,0 +1,187 @@
+use askama::Template;
+use async_stream::stream;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use chrono::TimeDelta;
+use datastar::{
+    Sse,
+    axum::ReadSignals,
+    prelude::{MergeSignals, RemoveFragments},
+};
+use serde::Deserialize;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthSession, ClientInfo},
+    csrf::CsrfToken,
+    fragments::{
+        AccessTokenFragment, FormMessageFragment, NewAccessTokenFragment, access_token_selector,
+    },
+    headers::CspNonce,
+    model::{self, AuditEvent, TokenScope},
+    service::{AccessTokenError, AccessTokenService, AuditLogService},
+};
+
+const MESSAGE_ID: &str = "access-token-message";
+
+#[derive(Template)]
+#[template(path = "access-tokens.html")]
+pub(crate) struct AccessTokens {
+    title: String,
+    partial: bool,
+    csrf_token: String,
+    csp_nonce: String,
+    tokens: Vec<model::AccessToken>,
+}
+
+pub(crate) async fn access_tokens(
+    State(tokens): State<AccessTokenService>,
+    auth_session: AuthSession,
+    CsrfToken(csrf_token): CsrfToken,
+    CspNonce(csp_nonce): CspNonce,
+) -> Response {
+    let user = auth_session.user.unwrap();
+
+    let tokens = match tokens.get_tokens(user.id).await {
+        Ok(tokens) => tokens,
+        Err(error) => {
+            error!("Failed to get access tokens: {}", error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    Html(
+        AccessTokens {
+            title: "Access tokens".to_owned(),
+            partial: false,
+            csrf_token,
+            csp_nonce,
+            tokens,
+        }
+        .render()
+        .unwrap(),
+    )
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct NewTokenSignals {
+    pub name: String,
+    pub scope: String,
+    /// Lifetime in days, tokens expire within a year.
+    pub expiry: String,
+}
+
+pub(crate) async fn create_token(
+    State(tokens): State<AccessTokenService>,
+    State(audit): State<AuditLogService>,
+    auth_session: AuthSession,
+    client: ClientInfo,
+    ReadSignals(signals): ReadSignals<NewTokenSignals>,
+) -> impl IntoResponse {
+    let user = auth_session
+        .user
+        .expect("User must be logged in to use this endpoint");
+
+    let name = signals.name.trim().to_string();
+    let scopes = match signals.scope.parse() {
+        Ok(TokenScope::Read) => vec![TokenScope::Read],
+        Ok(TokenScope::Write) => vec![TokenScope::Read, TokenScope::Write],
+        Err(_) => Vec::new(),
+    };
+    let expires_in = signals.expiry.parse().ok().and_then(TimeDelta::try_days);
+
+    let mut message = if name.is_empty() {
+        Some("The token needs a name.")
+    } else if scopes.is_empty() || expires_in.is_none() {
+        Some("Invalid scope or expiry.")
+    } else {
+        None
+    };
+
+    let created = match (message, expires_in) {
+        (None, Some(expires_in)) => {
+            match tokens
+                .create_token(user.id, &name, &scopes, expires_in)
+                .await
+            {
+                Ok(created) => Some(created),
+                Err(AccessTokenError::InvalidLifetime(_)) => {
+                    message = Some("Invalid scope or expiry.");
+                    None
+                }
+                Err(error) => {
+                    error!("Failed to create access token: {}", error);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    if let Some((token, _)) = &created {
+        let record = client
+            .audit(AuditEvent::TokenCreated)
+            .user(user.id)
+            .details(format!(
+                "{} '{}' with scopes {}",
+                token.id, token.name, token.scopes
+            ));
+        audit.record(record).await;
+    }
+
+    Sse(stream! {
+        match created {
+            Some((token, secret)) => {
+                yield MergeSignals::new("{ name: '' }").into();
+                yield FormMessageFragment::success(MESSAGE_ID, "").fragment().unwrap().into();
+                yield NewAccessTokenFragment { name, secret }.fragment().unwrap().into();
+                yield AccessTokenFragment { token }.fragment().unwrap().into();
+            }
+            None => {
+                let message = message.unwrap_or("Unable to create the token, please try again.");
+                yield FormMessageFragment::error(MESSAGE_ID, message).fragment().unwrap().into();
+            }
+        }
+    })
+}
+
+pub(crate) async fn revoke_token(
+    Path(id): Path<Uuid>,
+    State(tokens): State<AccessTokenService>,
+    State(audit): State<AuditLogService>,
+    auth_session: AuthSession,
+    client: ClientInfo,
+) -> impl IntoResponse {
+    let user = auth_session
+        .user
+        .expect("User must be logged in to use this endpoint");
+
+    let revoked = tokens
+        .revoke_token(user.id, id)
+        .await
+        .inspect_err(|error| error!("Failed to revoke access token: {}", error));
+    if revoked.as_ref().is_ok_and(|revoked| *revoked > 0) {
+        let record = client
+            .audit(AuditEvent::TokenRevoked)
+            .user(user.id)
+            .details(id);
+        audit.record(record).await;
+    }
+
+    Sse(stream! {
+        match revoked {
+            Ok(_) => {
+                yield RemoveFragments::new(access_token_selector(&id)).into();
+            }
+            Err(_) => {
+                let message = "Unable to revoke the token, please try again.";
+                yield FormMessageFragment::error(MESSAGE_ID, message).fragment().unwrap().into();
+            }
+        }
+    })
+}